@@ -0,0 +1,109 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+use xcap::XCapResult;
+
+/// 按标题、进程名、进程 id 或窗口 id 选择应用窗口，同时指定多个条件时需要全部满足
+///
+/// `title` 和 `app` 为不区分大小写的子串匹配。
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct WindowSelector {
+    pub title: Option<String>,
+    pub app: Option<String>,
+    pub pid: Option<u32>,
+    pub id: Option<u32>,
+}
+
+impl WindowSelector {
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none() && self.app.is_none() && self.pid.is_none() && self.id.is_none()
+    }
+
+    fn matches(&self, window: &xcap::Window) -> XCapResult<bool> {
+        if let Some(id) = self.id {
+            if window.id()? != id {
+                return Ok(false);
+            }
+        }
+        if let Some(pid) = self.pid {
+            if window.pid()? != pid {
+                return Ok(false);
+            }
+        }
+        if let Some(title) = &self.title {
+            if !contains_ignore_case(&window.title()?, title) {
+                return Ok(false);
+            }
+        }
+        if let Some(app) = &self.app {
+            if !contains_ignore_case(&window.app_name()?, app) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WindowInfo {
+    pub id: u32,
+    pub pid: u32,
+    pub app_name: String,
+    pub title: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub is_minimized: bool,
+}
+
+impl WindowInfo {
+    fn from_xcap(window: &xcap::Window) -> XCapResult<Self> {
+        Ok(WindowInfo {
+            id: window.id()?,
+            pid: window.pid()?,
+            app_name: window.app_name()?,
+            title: window.title()?,
+            x: window.x()?,
+            y: window.y()?,
+            width: window.width()?,
+            height: window.height()?,
+            is_minimized: window.is_minimized()?,
+        })
+    }
+}
+
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(&needle.to_lowercase())
+}
+
+pub fn list_windows() -> XCapResult<Vec<WindowInfo>> {
+    xcap::Window::all()?
+        .iter()
+        .map(WindowInfo::from_xcap)
+        .collect()
+}
+
+/// 截取第一个满足条件的窗口，窗口按从上到下的顺序匹配，跳过最小化的窗口
+///
+/// 直接读取窗口自身的内容，被其他窗口遮挡或不在覆盖窗口下方时也可以截取。
+/// 没有匹配的窗口时返回 `Ok(None)`。
+pub fn capture_window(selector: &WindowSelector) -> XCapResult<Option<RgbaImage>> {
+    for window in xcap::Window::all()? {
+        if window.is_minimized()? || !selector.matches(&window)? {
+            continue;
+        }
+        return window.capture_image().map(Some);
+    }
+    Ok(None)
+}
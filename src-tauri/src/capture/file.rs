@@ -0,0 +1,293 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+use super::{CaptureBackend, ScreenRect};
+use image::{ImageFormat, RgbaImage};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime};
+use xcap::{XCapError, XCapResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileMode {
+    /// 按文件名顺序循环播放目录中的图片
+    Loop,
+    /// 总是返回目录中修改时间最新的图片
+    Latest,
+}
+
+impl FileMode {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "loop" => Some(FileMode::Loop),
+            "latest" => Some(FileMode::Latest),
+            _ => None,
+        }
+    }
+}
+
+/// 从图片文件或目录读取画面的后端，不读取屏幕
+///
+/// `path` 为单个文件时每次都重新读取该文件；为目录时按 `mode` 选择文件。
+/// `Loop` 模式下 `fps` 大于 0 时按时间推进帧，否则每次截图前进一帧。
+pub struct FileBackend {
+    path: PathBuf,
+    mode: FileMode,
+    fps: f32,
+    started_at: Instant,
+    frame: AtomicU64,
+}
+
+impl FileBackend {
+    pub fn new(path: PathBuf, mode: FileMode, fps: f32) -> Self {
+        FileBackend {
+            path,
+            mode,
+            fps,
+            started_at: Instant::now(),
+            frame: AtomicU64::new(0),
+        }
+    }
+
+    fn select_file(&self) -> XCapResult<PathBuf> {
+        if !self.path.is_dir() {
+            return Ok(self.path.clone());
+        }
+
+        let mut files = list_images(&self.path)?;
+        if files.is_empty() {
+            return Err(XCapError::new(format!(
+                "No image files in {}",
+                self.path.display()
+            )));
+        }
+
+        match self.mode {
+            FileMode::Loop => {
+                files.sort_by(|a, b| a.0.cmp(&b.0));
+                let frame = if self.fps > 0.0 {
+                    (self.started_at.elapsed().as_secs_f64() * self.fps as f64) as u64
+                } else {
+                    self.frame.fetch_add(1, Ordering::SeqCst)
+                };
+                let index = (frame % files.len() as u64) as usize;
+                Ok(files.swap_remove(index).0)
+            }
+            FileMode::Latest => {
+                let latest = files
+                    .into_iter()
+                    .max_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)))
+                    .unwrap();
+                Ok(latest.0)
+            }
+        }
+    }
+}
+
+impl CaptureBackend for FileBackend {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn reads_screen(&self) -> bool {
+        false
+    }
+
+    /// 返回图片原始尺寸，忽略 `rect`
+    fn capture_rect(&self, _rect: ScreenRect) -> XCapResult<RgbaImage> {
+        let path = self.select_file()?;
+        let image =
+            image::open(&path).map_err(|e| XCapError::new(format!("{}: {}", path.display(), e)))?;
+        Ok(image.into_rgba8())
+    }
+}
+
+/// 目录中可以解码的图片文件及其修改时间
+fn list_images(dir: &Path) -> XCapResult<Vec<(PathBuf, SystemTime)>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).map_err(XCapError::new)? {
+        let entry = entry.map_err(XCapError::new)?;
+        let path = entry.path();
+        let readable = ImageFormat::from_path(&path)
+            .map(|format| format.reading_enabled())
+            .unwrap_or(false);
+        if !readable {
+            continue;
+        }
+        let metadata = entry.metadata().map_err(XCapError::new)?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+        files.push((path, modified));
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use std::time::Duration;
+
+    /// 测试结束时删除的临时目录
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("file_backend_{}_{}", name, std::process::id()));
+            fs::remove_dir_all(&path).ok();
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        /// 写入一张 1x1 的图片，像素的红色通道为 `red`
+        fn image(&self, name: &str, red: u8) -> PathBuf {
+            let path = self.0.join(name);
+            RgbaImage::from_pixel(1, 1, Rgba([red, 0, 0, 255]))
+                .save(&path)
+                .unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    fn set_modified(path: &Path, seconds: u64) {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    /// 文件后端忽略截取的区域
+    const RECT: ScreenRect = ScreenRect {
+        x: 0,
+        y: 0,
+        width: 1,
+        height: 1,
+    };
+
+    fn file_name(path: &Path) -> &str {
+        path.file_name().unwrap().to_str().unwrap()
+    }
+
+    fn red(backend: &FileBackend) -> u8 {
+        backend.capture_rect(RECT).unwrap().get_pixel(0, 0).0[0]
+    }
+
+    #[test]
+    fn loop_plays_files_in_name_order_and_wraps() {
+        let dir = TempDir::new("loop");
+        dir.image("b.png", 2);
+        dir.image("a.png", 1);
+        dir.image("c.png", 3);
+        fs::write(dir.0.join("notes.txt"), "not an image").unwrap();
+        fs::create_dir(dir.0.join("d.png")).unwrap();
+
+        let backend = FileBackend::new(dir.0.clone(), FileMode::Loop, 0.0);
+        let frames: Vec<u8> = (0..7).map(|_| red(&backend)).collect();
+        assert_eq!(frames, [1, 2, 3, 1, 2, 3, 1]);
+    }
+
+    #[test]
+    fn loop_with_fps_selects_frames_by_elapsed_time() {
+        let dir = TempDir::new("fps");
+        dir.image("0.png", 0);
+        dir.image("1.png", 1);
+        dir.image("2.png", 2);
+
+        let backend_at = |elapsed_ms: u64| FileBackend {
+            started_at: Instant::now() - Duration::from_millis(elapsed_ms),
+            ..FileBackend::new(dir.0.clone(), FileMode::Loop, 2.0)
+        };
+        assert_eq!(red(&backend_at(0)), 0);
+        assert_eq!(red(&backend_at(600)), 1);
+        assert_eq!(red(&backend_at(1100)), 2);
+        assert_eq!(red(&backend_at(1600)), 0);
+
+        // 按时间选择时，重复截图不会前进
+        let backend = backend_at(600);
+        assert_eq!(red(&backend), 1);
+        assert_eq!(red(&backend), 1);
+    }
+
+    #[test]
+    fn latest_picks_the_newest_file() {
+        let dir = TempDir::new("latest");
+        let old = dir.image("z.png", 1);
+        let new = dir.image("a.png", 2);
+        set_modified(&old, 1_000);
+        set_modified(&new, 2_000);
+
+        let backend = FileBackend::new(dir.0.clone(), FileMode::Latest, 0.0);
+        assert_eq!(file_name(&backend.select_file().unwrap()), "a.png");
+        assert_eq!(red(&backend), 2);
+
+        set_modified(&old, 3_000);
+        assert_eq!(file_name(&backend.select_file().unwrap()), "z.png");
+    }
+
+    #[test]
+    fn latest_breaks_ties_by_file_name() {
+        let dir = TempDir::new("tie");
+        for name in ["b.png", "c.png", "a.png"] {
+            let path = dir.image(name, 0);
+            set_modified(&path, 1_000);
+        }
+
+        let backend = FileBackend::new(dir.0.clone(), FileMode::Latest, 0.0);
+        assert_eq!(file_name(&backend.select_file().unwrap()), "c.png");
+    }
+
+    #[test]
+    fn empty_directory_is_an_error() {
+        let dir = TempDir::new("empty");
+        fs::write(dir.0.join("notes.txt"), "not an image").unwrap();
+        for mode in [FileMode::Loop, FileMode::Latest] {
+            let backend = FileBackend::new(dir.0.clone(), mode, 0.0);
+            assert!(backend.capture_rect(RECT).is_err());
+        }
+    }
+
+    #[test]
+    fn single_file_is_read_on_every_capture() {
+        let dir = TempDir::new("single");
+        let path = dir.image("frame.png", 7);
+
+        let backend = FileBackend::new(path.clone(), FileMode::Loop, 0.0);
+        assert_eq!(red(&backend), 7);
+        assert_eq!(red(&backend), 7);
+
+        dir.image("frame.png", 8);
+        assert_eq!(red(&backend), 8);
+
+        fs::remove_file(&path).unwrap();
+        assert!(backend.select_file().is_ok());
+        assert!(backend.capture_rect(RECT).is_err());
+    }
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!(FileMode::parse("loop"), Some(FileMode::Loop));
+        assert_eq!(FileMode::parse("latest"), Some(FileMode::Latest));
+        assert_eq!(FileMode::parse("newest"), None);
+    }
+}
@@ -0,0 +1,32 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+use super::{CaptureBackend, CursorImage, ScreenRect};
+use crate::windows_utils::capture::{capture_cursor, capture_rect};
+use image::RgbaImage;
+use xcap::XCapResult;
+
+/// 基于 GDI BitBlt 的截图后端
+pub struct GdiBackend;
+
+impl CaptureBackend for GdiBackend {
+    fn name(&self) -> &'static str {
+        "gdi"
+    }
+
+    fn capture_rect(&self, rect: ScreenRect) -> XCapResult<RgbaImage> {
+        capture_rect(rect.x, rect.y, rect.width as i32, rect.height as i32)
+    }
+
+    fn cursor(&self) -> XCapResult<Option<CursorImage>> {
+        Ok(capture_cursor()?.map(|(x, y, image)| CursorImage { x, y, image }))
+    }
+}
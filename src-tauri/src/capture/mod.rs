@@ -0,0 +1,400 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+pub mod app_window;
+mod file;
+#[cfg(target_os = "windows")]
+mod gdi;
+pub mod monitor;
+mod synthetic;
+#[cfg(target_os = "linux")]
+mod x11;
+
+pub use crate::geometry::ScreenRect;
+use crate::geometry::{restore_physical_size, MonitorGeometry};
+use crate::{config, RecorderState};
+use image::RgbaImage;
+use std::sync::Arc;
+use tauri::{Manager, Window};
+use xcap::{XCapError, XCapResult};
+
+/// 鼠标指针图像，`x`、`y` 为图像左上角（已减去热点偏移）在屏幕上的位置
+pub struct CursorImage {
+    pub x: i32,
+    pub y: i32,
+    pub image: RgbaImage,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CaptureOptions {
+    /// 将当前鼠标指针绘制到截图中
+    pub include_cursor: bool,
+}
+
+/// 截图后端：给定屏幕区域，返回该区域的像素
+///
+/// 调用 `capture_rect` 之前，覆盖窗口已经由 `capture_screen` 隐藏，
+/// 后端只需要读取对应区域即可。
+pub trait CaptureBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// 是否读取真实的屏幕像素，为 `false` 时截图不需要隐藏覆盖窗口
+    fn reads_screen(&self) -> bool {
+        true
+    }
+
+    fn capture_rect(&self, rect: ScreenRect) -> XCapResult<RgbaImage>;
+
+    /// 当前鼠标指针，指针隐藏或后端不支持时返回 `Ok(None)`
+    fn cursor(&self) -> XCapResult<Option<CursorImage>> {
+        Ok(None)
+    }
+}
+
+pub type SharedCaptureBackend = Arc<dyn CaptureBackend>;
+
+/// 当前平台没有可用后端时使用，所有截图请求都会返回错误
+#[cfg(not(target_os = "windows"))]
+struct UnsupportedBackend;
+
+#[cfg(not(target_os = "windows"))]
+impl CaptureBackend for UnsupportedBackend {
+    fn name(&self) -> &'static str {
+        "unsupported"
+    }
+
+    fn capture_rect(&self, _rect: ScreenRect) -> XCapResult<RgbaImage> {
+        Err(XCapError::new(
+            "No capture backend available on this platform",
+        ))
+    }
+}
+
+/// 根据 `--capture-backend` / `COMFY_CAPTURER_BACKEND` 选择后端，未指定时使用平台默认后端
+///
+/// `synthetic` 后端的图像大小可以通过 `--synthetic-size=WxH` / `COMFY_CAPTURER_SYNTHETIC_SIZE` 指定。
+/// `file` 后端通过 `--capture-path`、`--capture-mode=loop|latest`、`--capture-fps` 配置，
+/// 对应的环境变量为 `COMFY_CAPTURER_PATH`、`COMFY_CAPTURER_MODE`、`COMFY_CAPTURER_FPS`。
+pub fn backend_from_config() -> SharedCaptureBackend {
+    let name = config::option("capture-backend", "COMFY_CAPTURER_BACKEND");
+    match name.as_deref() {
+        None => default_backend(),
+        Some("synthetic") => {
+            let size = config::option("synthetic-size", "COMFY_CAPTURER_SYNTHETIC_SIZE")
+                .and_then(|size| parse_size(&size));
+            Arc::new(synthetic::SyntheticBackend::new(size))
+        }
+        Some("file") => match file_backend_from_config() {
+            Some(backend) => Arc::new(backend),
+            None => {
+                tracing::error!("file capture backend requires --capture-path");
+                default_backend()
+            }
+        },
+        Some(name) => {
+            let backend = default_backend();
+            if name != backend.name() {
+                tracing::error!(
+                    "unknown capture backend {:?}, using {:?}",
+                    name,
+                    backend.name()
+                );
+            }
+            backend
+        }
+    }
+}
+
+fn file_backend_from_config() -> Option<file::FileBackend> {
+    let path = config::option("capture-path", "COMFY_CAPTURER_PATH")?;
+    let mode = match config::option("capture-mode", "COMFY_CAPTURER_MODE") {
+        None => file::FileMode::Loop,
+        Some(mode) => file::FileMode::parse(&mode).unwrap_or_else(|| {
+            tracing::error!("unknown capture mode {:?}, using \"loop\"", mode);
+            file::FileMode::Loop
+        }),
+    };
+    let fps = config::option("capture-fps", "COMFY_CAPTURER_FPS")
+        .and_then(|fps| fps.parse().ok())
+        .unwrap_or(0.0);
+    Some(file::FileBackend::new(path.into(), mode, fps))
+}
+
+/// 解析 `512x512` 形式的尺寸
+fn parse_size(size: &str) -> Option<(u32, u32)> {
+    let (width, height) = size.split_once(['x', 'X'])?;
+    let width = width.trim().parse().ok()?;
+    let height = height.trim().parse().ok()?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+    Some((width, height))
+}
+
+pub fn default_backend() -> SharedCaptureBackend {
+    #[cfg(target_os = "windows")]
+    {
+        Arc::new(gdi::GdiBackend)
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        match x11::X11Backend::connect() {
+            Ok(backend) => Arc::new(backend),
+            Err(e) => {
+                tracing::error!("X11 capture backend unavailable: {:?}", e);
+                Arc::new(UnsupportedBackend)
+            }
+        }
+    }
+
+    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+    {
+        Arc::new(UnsupportedBackend)
+    }
+}
+
+/// 截取覆盖窗口客户区下方的屏幕内容
+pub fn capture_screen(
+    backend: &dyn CaptureBackend,
+    window: &Window,
+    options: CaptureOptions,
+) -> XCapResult<RgbaImage> {
+    let rect = overlay_screen_rect(window)?;
+    capture_region(backend, window, rect, options)
+}
+
+/// 截取屏幕上任意区域，截图期间同样隐藏覆盖窗口，但不移动它
+pub fn capture_region(
+    backend: &dyn CaptureBackend,
+    window: &Window,
+    rect: ScreenRect,
+    options: CaptureOptions,
+) -> XCapResult<RgbaImage> {
+    capture_region_hiding(backend, std::slice::from_ref(window), rect, options)
+}
+
+/// 截图期间隐藏 `windows` 中的所有覆盖窗口，用于可能同时包含多个区域窗口的截图（例如整个显示器）
+pub fn capture_region_hiding(
+    backend: &dyn CaptureBackend,
+    windows: &[Window],
+    rect: ScreenRect,
+    options: CaptureOptions,
+) -> XCapResult<RgbaImage> {
+    let mut image = if backend.reads_screen() {
+        let _hidden = hide_overlays(windows)?;
+        backend.capture_rect(rect)?
+    } else {
+        backend.capture_rect(rect)?
+    };
+
+    if options.include_cursor {
+        if let Some(cursor) = backend.cursor()? {
+            composite_cursor(&mut image, rect, &cursor);
+        }
+    }
+
+    Ok(image)
+}
+
+/// 按 alpha 将指针叠加到 `rect` 区域的截图上，指针不在区域内时不做任何事
+fn composite_cursor(image: &mut RgbaImage, rect: ScreenRect, cursor: &CursorImage) {
+    let x = (cursor.x - rect.x) as i64;
+    let y = (cursor.y - rect.y) as i64;
+    image::imageops::overlay(image, &cursor.image, x, y);
+}
+
+/// 覆盖窗口所在显示器的范围
+#[cfg(target_os = "windows")]
+pub fn overlay_monitor_rect(window: &Window) -> XCapResult<ScreenRect> {
+    use crate::windows_utils::capture::get_monitor_rect;
+
+    let hwnd = window.hwnd().map_err(XCapError::new)?;
+    let (left, top, right, bottom) = get_monitor_rect(hwnd);
+    Ok(ScreenRect {
+        x: left,
+        y: top,
+        width: (right - left).max(0) as u32,
+        height: (bottom - top).max(0) as u32,
+    })
+}
+
+#[cfg(not(target_os = "windows"))]
+pub fn overlay_monitor_rect(window: &Window) -> XCapResult<ScreenRect> {
+    let monitor = window
+        .current_monitor()
+        .map_err(XCapError::new)?
+        .ok_or_else(|| XCapError::new("Overlay window is not on any monitor"))?;
+    Ok(ScreenRect {
+        x: monitor.position().x,
+        y: monitor.position().y,
+        width: monitor.size().width,
+        height: monitor.size().height,
+    })
+}
+
+/// 覆盖窗口客户区的物理范围
+///
+/// 窗口由 `resize_window` 按 `snap` 设置过物理尺寸时，消除逻辑尺寸换算带来的取整误差，
+/// 截图尺寸与设置的尺寸一致。
+pub fn overlay_screen_rect(window: &Window) -> XCapResult<ScreenRect> {
+    let rect = client_screen_rect(window)?;
+    let snapped_size = window
+        .state::<RecorderState>()
+        .snapped_sizes
+        .lock()
+        .unwrap()
+        .get(window.label())
+        .copied();
+    match snapped_size {
+        Some(size) => Ok(restore_physical_size(
+            &monitor_geometries(window)?,
+            rect,
+            size,
+        )),
+        None => Ok(rect),
+    }
+}
+
+/// `GetClientRect` 的尺寸按客户区左上角所在显示器当前的缩放比例换算，
+/// 窗口拖到另一块缩放比例不同的显示器后同样正确。
+#[cfg(target_os = "windows")]
+fn client_screen_rect(window: &Window) -> XCapResult<ScreenRect> {
+    use crate::geometry::client_rect_to_physical;
+    use crate::windows_utils::capture::get_client_screen_rect;
+
+    let hwnd = window.hwnd().map_err(XCapError::new)?;
+    let (x, y, width, height) = get_client_screen_rect(hwnd);
+    let monitors = monitor_geometries(window)?;
+    Ok(client_rect_to_physical(
+        &monitors,
+        x,
+        y,
+        width.max(0) as f64,
+        height.max(0) as f64,
+    ))
+}
+
+/// tauri 返回的客户区位置和大小已经是物理像素，不需要再换算
+#[cfg(not(target_os = "windows"))]
+fn client_screen_rect(window: &Window) -> XCapResult<ScreenRect> {
+    let position = window.inner_position().map_err(XCapError::new)?;
+    let size = window.inner_size().map_err(XCapError::new)?;
+    Ok(ScreenRect {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+    })
+}
+
+/// 所有显示器的物理范围和截图时的缩放比例
+pub fn monitor_geometries(window: &Window) -> XCapResult<Vec<MonitorGeometry>> {
+    Ok(window
+        .available_monitors()
+        .map_err(XCapError::new)?
+        .iter()
+        .map(|monitor| MonitorGeometry {
+            bounds: ScreenRect {
+                x: monitor.position().x,
+                y: monitor.position().y,
+                width: monitor.size().width,
+                height: monitor.size().height,
+            },
+            scale_factor: monitor.scale_factor(),
+        })
+        .collect())
+}
+
+/// 截图期间隐藏覆盖窗口，返回的 guard 被 drop 时恢复显示；中途失败时已隐藏的窗口同样恢复
+#[cfg(target_os = "windows")]
+fn hide_overlays(windows: &[Window]) -> XCapResult<impl Drop> {
+    use crate::windows_utils::capture::{hide_window, restore_window};
+
+    let mut hidden = scopeguard::guard(Vec::new(), |hidden: Vec<_>| {
+        for (hwnd, ex_style) in hidden {
+            restore_window(hwnd, ex_style);
+        }
+    });
+    for window in windows {
+        let hwnd = window.hwnd().map_err(XCapError::new)?;
+        hidden.push((hwnd, hide_window(hwnd)));
+    }
+    Ok(hidden)
+}
+
+/// 非 Windows 平台通过 tauri 隐藏窗口，隐藏请求由事件循环异步处理，需要等待窗口真正消失
+#[cfg(not(target_os = "windows"))]
+const OVERLAY_HIDE_DELAY: std::time::Duration = std::time::Duration::from_millis(100);
+
+#[cfg(not(target_os = "windows"))]
+fn hide_overlays(windows: &[Window]) -> XCapResult<impl Drop> {
+    let mut hidden = scopeguard::guard(Vec::new(), |hidden: Vec<Window>| {
+        for window in hidden {
+            if let Err(e) = window.show() {
+                tracing::error!("show overlay window failed: {:?}", e);
+            }
+        }
+    });
+    for window in windows {
+        window.hide().map_err(XCapError::new)?;
+        hidden.push(window.clone());
+    }
+    // 所有窗口的隐藏请求一起等待
+    std::thread::sleep(OVERLAY_HIDE_DELAY);
+    Ok(hidden)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+
+    fn cursor_at(x: i32, y: i32) -> CursorImage {
+        CursorImage {
+            x,
+            y,
+            image: RgbaImage::from_pixel(2, 2, Rgba(WHITE)),
+        }
+    }
+
+    #[test]
+    fn composites_cursor_relative_to_rect() {
+        let rect = ScreenRect {
+            x: -100,
+            y: 50,
+            width: 10,
+            height: 10,
+        };
+        let mut image = RgbaImage::from_pixel(10, 10, Rgba(BLACK));
+        composite_cursor(&mut image, rect, &cursor_at(-97, 52));
+        assert_eq!(image.get_pixel(3, 2).0, WHITE);
+        assert_eq!(image.get_pixel(4, 3).0, WHITE);
+        assert_eq!(image.get_pixel(2, 2).0, BLACK);
+        assert_eq!(image.get_pixel(5, 4).0, BLACK);
+    }
+
+    #[test]
+    fn ignores_cursor_outside_rect() {
+        let rect = ScreenRect {
+            x: 0,
+            y: 0,
+            width: 10,
+            height: 10,
+        };
+        let mut image = RgbaImage::from_pixel(10, 10, Rgba(BLACK));
+        composite_cursor(&mut image, rect, &cursor_at(20, -5));
+        assert!(image.pixels().all(|pixel| pixel.0 == BLACK));
+    }
+}
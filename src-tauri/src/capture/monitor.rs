@@ -0,0 +1,78 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+use super::ScreenRect;
+use serde::Serialize;
+use xcap::XCapResult;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MonitorInfo {
+    pub index: usize,
+    pub id: u32,
+    pub name: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub scale_factor: f32,
+    pub is_primary: bool,
+}
+
+impl MonitorInfo {
+    fn from_xcap(index: usize, monitor: &xcap::Monitor) -> XCapResult<Self> {
+        Ok(MonitorInfo {
+            index,
+            id: monitor.id()?,
+            name: monitor.name()?,
+            x: monitor.x()?,
+            y: monitor.y()?,
+            width: monitor.width()?,
+            height: monitor.height()?,
+            scale_factor: monitor.scale_factor()?,
+            is_primary: monitor.is_primary()?,
+        })
+    }
+
+    fn rect(&self) -> ScreenRect {
+        ScreenRect {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+/// 所有显示器，`index` 为 `/capture_monitor/{index}` 和 `monitor=` 参数使用的序号
+pub fn list_monitors() -> XCapResult<Vec<MonitorInfo>> {
+    xcap::Monitor::all()?
+        .iter()
+        .enumerate()
+        .map(|(index, monitor)| MonitorInfo::from_xcap(index, monitor))
+        .collect()
+}
+
+/// 所有显示器在虚拟桌面上的范围，顺序与 `list_monitors` 一致
+pub fn monitor_rects() -> XCapResult<Vec<ScreenRect>> {
+    Ok(list_monitors()?.iter().map(MonitorInfo::rect).collect())
+}
+
+/// `key` 为序号或显示器名称（不区分大小写），找不到时返回 `Ok(None)`
+pub fn find_monitor_rect(key: &str) -> XCapResult<Option<ScreenRect>> {
+    let monitors = list_monitors()?;
+    let monitor = match key.parse::<usize>() {
+        Ok(index) => monitors.get(index),
+        Err(_) => monitors
+            .iter()
+            .find(|monitor| monitor.name.eq_ignore_ascii_case(key)),
+    };
+    Ok(monitor.map(MonitorInfo::rect))
+}
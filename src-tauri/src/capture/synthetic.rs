@@ -0,0 +1,211 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+use super::{CaptureBackend, ScreenRect};
+use image::{Rgba, RgbaImage};
+use std::sync::atomic::{AtomicU64, Ordering};
+use xcap::XCapResult;
+
+/// 75% 彩条，从左到右：白、黄、青、绿、品红、红、蓝
+const COLOR_BARS: [[u8; 3]; 7] = [
+    [191, 191, 191],
+    [191, 191, 0],
+    [0, 191, 191],
+    [0, 191, 0],
+    [191, 0, 191],
+    [191, 0, 0],
+    [0, 0, 191],
+];
+
+/// 3x5 点阵数字，每行低 3 位有效，最高位在左
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+const DIGIT_SCALE: u32 = 4;
+
+/// 每帧渐变移动的像素数
+const GRADIENT_STEP: u32 = 8;
+
+/// 生成确定性测试图案的后端，不读取屏幕，用于没有显示器的环境
+///
+/// 上 2/3 为彩条，下 1/3 为随帧号水平移动的渐变，左上角绘制帧号。
+/// 同一帧号、同一尺寸得到的图像总是相同的。
+pub struct SyntheticBackend {
+    size: Option<(u32, u32)>,
+    frame: AtomicU64,
+}
+
+impl SyntheticBackend {
+    /// `size` 为 `None` 时使用请求区域的大小
+    pub fn new(size: Option<(u32, u32)>) -> Self {
+        SyntheticBackend {
+            size,
+            frame: AtomicU64::new(0),
+        }
+    }
+}
+
+impl CaptureBackend for SyntheticBackend {
+    fn name(&self) -> &'static str {
+        "synthetic"
+    }
+
+    fn reads_screen(&self) -> bool {
+        false
+    }
+
+    fn capture_rect(&self, rect: ScreenRect) -> XCapResult<RgbaImage> {
+        let (width, height) = self.size.unwrap_or((rect.width, rect.height));
+        let frame = self.frame.fetch_add(1, Ordering::SeqCst);
+        Ok(render_frame(width, height, frame))
+    }
+}
+
+fn render_frame(width: u32, height: u32, frame: u64) -> RgbaImage {
+    let bars_height = height * 2 / 3;
+    let shift = (frame % width.max(1) as u64) as u32 * GRADIENT_STEP;
+
+    let mut image = RgbaImage::from_fn(width, height, |x, y| {
+        if y < bars_height {
+            let [r, g, b] = COLOR_BARS[(x * COLOR_BARS.len() as u32 / width) as usize];
+            Rgba([r, g, b, 255])
+        } else {
+            let value = ((x + shift) % width * 255 / width.max(1)) as u8;
+            Rgba([value, value, 255 - value, 255])
+        }
+    });
+
+    draw_number(&mut image, frame, DIGIT_SCALE * 2, DIGIT_SCALE * 2);
+
+    image
+}
+
+fn draw_number(image: &mut RgbaImage, number: u64, left: u32, top: u32) {
+    let digits = number.to_string();
+    let advance = 4 * DIGIT_SCALE;
+
+    // 黑底白字，保证在任何背景上都能读出帧号
+    let box_width = digits.len() as u32 * advance;
+    let box_height = 6 * DIGIT_SCALE;
+    for y in top.saturating_sub(DIGIT_SCALE)..(top + box_height).min(image.height()) {
+        for x in left.saturating_sub(DIGIT_SCALE)..(left + box_width).min(image.width()) {
+            image.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+        }
+    }
+
+    for (i, digit) in digits.bytes().enumerate() {
+        let glyph = DIGITS[(digit - b'0') as usize];
+        let glyph_left = left + i as u32 * advance;
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                for dy in 0..DIGIT_SCALE {
+                    for dx in 0..DIGIT_SCALE {
+                        let x = glyph_left + col * DIGIT_SCALE + dx;
+                        let y = top + row as u32 * DIGIT_SCALE + dy;
+                        if x < image.width() && y < image.height() {
+                            image.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(width: u32, height: u32) -> ScreenRect {
+        ScreenRect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn uses_requested_size_unless_overridden() {
+        let backend = SyntheticBackend::new(None);
+        let image = backend.capture_rect(rect(320, 200)).unwrap();
+        assert_eq!(image.dimensions(), (320, 200));
+
+        let backend = SyntheticBackend::new(Some((64, 48)));
+        let image = backend.capture_rect(rect(320, 200)).unwrap();
+        assert_eq!(image.dimensions(), (64, 48));
+    }
+
+    #[test]
+    fn frames_are_deterministic() {
+        let a = SyntheticBackend::new(None);
+        let b = SyntheticBackend::new(None);
+        for _ in 0..3 {
+            assert_eq!(
+                a.capture_rect(rect(160, 90)).unwrap(),
+                b.capture_rect(rect(160, 90)).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn consecutive_frames_differ() {
+        let backend = SyntheticBackend::new(None);
+        let first = backend.capture_rect(rect(160, 90)).unwrap();
+        let second = backend.capture_rect(rect(160, 90)).unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn draws_color_bars_above_gradient() {
+        let backend = SyntheticBackend::new(None);
+        let image = backend.capture_rect(rect(700, 300)).unwrap();
+        for (i, [r, g, b]) in COLOR_BARS.iter().enumerate() {
+            let x = i as u32 * 100 + 50;
+            assert_eq!(image.get_pixel(x, 150).0, [*r, *g, *b, 255]);
+        }
+        // 渐变区域的蓝色通道与红色通道互补
+        let pixel = image.get_pixel(350, 250).0;
+        assert_eq!(pixel[0] as u32 + pixel[2] as u32, 255);
+    }
+
+    #[test]
+    fn draws_frame_number_on_black_box() {
+        let backend = SyntheticBackend::new(None);
+        let image = backend.capture_rect(rect(160, 90)).unwrap();
+        // 帧号 0 的左上角笔画为白色，其外围为黑色
+        assert_eq!(
+            image.get_pixel(DIGIT_SCALE * 2, DIGIT_SCALE * 2).0,
+            [255; 4]
+        );
+        assert_eq!(image.get_pixel(DIGIT_SCALE, DIGIT_SCALE).0, [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn empty_rect_gives_empty_image() {
+        let backend = SyntheticBackend::new(None);
+        let image = backend.capture_rect(rect(0, 0)).unwrap();
+        assert_eq!(image.dimensions(), (0, 0));
+    }
+}
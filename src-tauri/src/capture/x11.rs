@@ -0,0 +1,202 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+use super::{CaptureBackend, CursorImage, ScreenRect};
+use image::RgbaImage;
+use x11rb::connection::Connection;
+use x11rb::protocol::xfixes::ConnectionExt as _;
+use x11rb::protocol::xproto::{ConnectionExt, ImageFormat, ImageOrder, Window};
+use x11rb::rust_connection::RustConnection;
+use xcap::{XCapError, XCapResult};
+
+/// 基于 X11 GetImage 的截图后端，直接读取 root window 的像素
+///
+/// 不依赖 GPU 或合成器，可以在 Xvfb 下运行。
+pub struct X11Backend {
+    conn: RustConnection,
+    root: Window,
+    /// XFixes 不可用时截图中无法包含鼠标指针
+    has_xfixes: bool,
+}
+
+impl X11Backend {
+    /// 连接 `$DISPLAY` 指定的 X server
+    pub fn connect() -> XCapResult<Self> {
+        let (conn, screen_num) = x11rb::connect(None).map_err(XCapError::new)?;
+
+        let setup = conn.setup();
+        if setup.image_byte_order != ImageOrder::LSB_FIRST {
+            return Err(XCapError::new("Unsupported X11 image byte order"));
+        }
+
+        let screen = &setup.roots[screen_num];
+        let bits_per_pixel = setup
+            .pixmap_formats
+            .iter()
+            .find(|format| format.depth == screen.root_depth)
+            .map(|format| format.bits_per_pixel);
+        if screen.root_depth < 24 || bits_per_pixel != Some(32) {
+            return Err(XCapError::new(format!(
+                "Unsupported X11 root depth {} ({:?} bits per pixel)",
+                screen.root_depth, bits_per_pixel
+            )));
+        }
+
+        let root = screen.root;
+
+        // 使用 XFixes 之前必须先协商版本
+        let has_xfixes = conn
+            .xfixes_query_version(4, 0)
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .is_some();
+
+        Ok(X11Backend {
+            conn,
+            root,
+            has_xfixes,
+        })
+    }
+}
+
+impl CaptureBackend for X11Backend {
+    fn name(&self) -> &'static str {
+        "x11"
+    }
+
+    fn capture_rect(&self, rect: ScreenRect) -> XCapResult<RgbaImage> {
+        let mut image = RgbaImage::new(rect.width, rect.height);
+
+        // 每次重新获取 root 大小，xrandr 调整分辨率或插拔显示器后依然正确
+        let root_geometry = self
+            .conn
+            .get_geometry(self.root)
+            .map_err(XCapError::new)?
+            .reply()
+            .map_err(XCapError::new)?;
+
+        // GetImage 超出 root window 会返回 BadMatch，只读取与屏幕相交的部分，其余保持透明
+        let left = rect.x.max(0);
+        let top = rect.y.max(0);
+        let right = rect.right().min(root_geometry.width as i64) as i32;
+        let bottom = rect.bottom().min(root_geometry.height as i64) as i32;
+        if right <= left || bottom <= top {
+            return Ok(image);
+        }
+
+        let width = (right - left) as u16;
+        let height = (bottom - top) as u16;
+        let reply = self
+            .conn
+            .get_image(
+                ImageFormat::Z_PIXMAP,
+                self.root,
+                left as i16,
+                top as i16,
+                width,
+                height,
+                !0,
+            )
+            .map_err(XCapError::new)?
+            .reply()
+            .map_err(XCapError::new)?;
+
+        // Z_PIXMAP 在 LSB 字节序、32 位像素下为 BGRX
+        let offset_x = (left - rect.x) as u32;
+        let offset_y = (top - rect.y) as u32;
+        for (i, src) in reply.data.chunks_exact(4).enumerate() {
+            let x = i as u32 % width as u32;
+            let y = i as u32 / width as u32;
+            image.put_pixel(
+                offset_x + x,
+                offset_y + y,
+                image::Rgba([src[2], src[1], src[0], 255]),
+            );
+        }
+
+        Ok(image)
+    }
+
+    fn cursor(&self) -> XCapResult<Option<CursorImage>> {
+        if !self.has_xfixes {
+            return Ok(None);
+        }
+
+        let reply = self
+            .conn
+            .xfixes_get_cursor_image()
+            .map_err(XCapError::new)?
+            .reply()
+            .map_err(XCapError::new)?;
+
+        // XFixes 返回预乘 alpha 的 ARGB，转换为非预乘的 RGBA
+        let mut image = RgbaImage::new(reply.width as u32, reply.height as u32);
+        for (pixel, argb) in image.pixels_mut().zip(reply.cursor_image.iter()) {
+            let [b, g, r, a] = argb.to_le_bytes();
+            let unpremultiply = |c: u8| {
+                if a == 0 {
+                    0
+                } else {
+                    (c as u32 * 255 / a as u32).min(255) as u8
+                }
+            };
+            *pixel = image::Rgba([unpremultiply(r), unpremultiply(g), unpremultiply(b), a]);
+        }
+
+        Ok(Some(CursorImage {
+            x: reply.x as i32 - reply.xhot as i32,
+            y: reply.y as i32 - reply.yhot as i32,
+            image,
+        }))
+    }
+}
+
+/// 需要 X server，例如 `xvfb-run cargo test`；没有设置 `DISPLAY` 时跳过
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn connect() -> Option<X11Backend> {
+        if std::env::var_os("DISPLAY").is_none() {
+            eprintln!("DISPLAY is not set, skipping X11 test");
+            return None;
+        }
+        Some(X11Backend::connect().expect("Failed to connect to $DISPLAY"))
+    }
+
+    #[test]
+    fn captures_requested_size_and_clips_offscreen_pixels() {
+        let Some(backend) = connect() else {
+            return;
+        };
+        let rect = ScreenRect {
+            x: -10,
+            y: -10,
+            width: 20,
+            height: 20,
+        };
+        let image = backend.capture_rect(rect).unwrap();
+        assert_eq!(image.dimensions(), (20, 20));
+        // 屏幕外的部分保持透明，屏幕内的部分不透明
+        assert_eq!(image.get_pixel(0, 0)[3], 0);
+        assert_eq!(image.get_pixel(15, 15)[3], 255);
+    }
+
+    #[test]
+    fn reads_cursor_through_xfixes() {
+        let Some(backend) = connect() else {
+            return;
+        };
+        assert!(backend.has_xfixes, "XFixes is not available");
+        let cursor = backend.cursor().unwrap().expect("No cursor image");
+        assert!(cursor.image.width() > 0 && cursor.image.height() > 0);
+    }
+}
@@ -0,0 +1,312 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+//! 比较两帧截图的像素差异
+
+use image::{GrayImage, Luma, RgbaImage};
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+/// 两帧截图的差异
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiffStats {
+    /// 任一通道不同的像素数
+    pub changed_pixels: u64,
+    pub total_pixels: u64,
+    /// 所有像素 RGBA 通道差值绝对值的平均，范围 0 到 255
+    pub mean_error: f64,
+}
+
+impl DiffStats {
+    /// 变化像素的比例，范围 0 到 1
+    pub fn changed_ratio(&self) -> f64 {
+        if self.total_pixels == 0 {
+            return 0.0;
+        }
+        self.changed_pixels as f64 / self.total_pixels as f64
+    }
+}
+
+/// 图片内容的哈希，用于快速判断画面是否变化
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+/// 逐像素比较，尺寸不同时视为所有像素都发生了变化
+pub fn compare(before: &RgbaImage, after: &RgbaImage) -> DiffStats {
+    if before.dimensions() != after.dimensions() {
+        let total_pixels = after.width() as u64 * after.height() as u64;
+        return DiffStats {
+            changed_pixels: total_pixels,
+            total_pixels,
+            mean_error: 255.0,
+        };
+    }
+
+    let mut changed_pixels = 0;
+    let mut error_sum = 0u64;
+    for (a, b) in before.pixels().zip(after.pixels()) {
+        if a != b {
+            changed_pixels += 1;
+            error_sum +=
+                a.0.iter()
+                    .zip(b.0.iter())
+                    .map(|(a, b)| a.abs_diff(*b) as u64)
+                    .sum::<u64>();
+        }
+    }
+
+    let total_pixels = before.width() as u64 * before.height() as u64;
+    let mean_error = if total_pixels == 0 {
+        0.0
+    } else {
+        error_sum as f64 / (total_pixels * 4) as f64
+    };
+    DiffStats {
+        changed_pixels,
+        total_pixels,
+        mean_error,
+    }
+}
+
+/// 图片坐标中的一块矩形区域
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DiffRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// 变化的像素为 255，其余为 0；尺寸不同时整张图都视为变化
+pub fn change_mask(before: &RgbaImage, after: &RgbaImage) -> GrayImage {
+    if before.dimensions() != after.dimensions() {
+        return GrayImage::from_pixel(after.width(), after.height(), Luma([255]));
+    }
+    GrayImage::from_fn(after.width(), after.height(), |x, y| {
+        if before.get_pixel(x, y) == after.get_pixel(x, y) {
+            Luma([0])
+        } else {
+            Luma([255])
+        }
+    })
+}
+
+/// 将变化的像素归并为矩形：按 `cell`×`cell` 划分网格，相互接触（含对角）的变化网格为一组，
+/// 每组返回其中变化像素的包围盒
+pub fn changed_regions(mask: &GrayImage, cell: u32) -> Vec<DiffRect> {
+    let cell = cell.max(1);
+    let columns = mask.width().div_ceil(cell) as usize;
+    let rows = mask.height().div_ceil(cell) as usize;
+
+    // 每个网格中变化像素的包围盒 (left, top, right, bottom)，右下为闭区间
+    let mut cells: Vec<Option<(u32, u32, u32, u32)>> = vec![None; columns * rows];
+    for (x, y, pixel) in mask.enumerate_pixels() {
+        if pixel.0[0] == 0 {
+            continue;
+        }
+        let index = (y / cell) as usize * columns + (x / cell) as usize;
+        cells[index] = Some(match cells[index] {
+            Some((left, top, right, bottom)) => {
+                (left.min(x), top.min(y), right.max(x), bottom.max(y))
+            }
+            None => (x, y, x, y),
+        });
+    }
+
+    let mut visited = vec![false; cells.len()];
+    let mut regions = Vec::new();
+    for start in 0..cells.len() {
+        if visited[start] || cells[start].is_none() {
+            continue;
+        }
+        visited[start] = true;
+        let mut stack = vec![start];
+        let mut bounds = cells[start].unwrap();
+        while let Some(index) = stack.pop() {
+            let (left, top, right, bottom) = cells[index].unwrap();
+            bounds = (
+                bounds.0.min(left),
+                bounds.1.min(top),
+                bounds.2.max(right),
+                bounds.3.max(bottom),
+            );
+
+            let (column, row) = (index % columns, index / columns);
+            for neighbor_row in row.saturating_sub(1)..=(row + 1).min(rows - 1) {
+                for neighbor_column in column.saturating_sub(1)..=(column + 1).min(columns - 1) {
+                    let neighbor = neighbor_row * columns + neighbor_column;
+                    if !visited[neighbor] && cells[neighbor].is_some() {
+                        visited[neighbor] = true;
+                        stack.push(neighbor);
+                    }
+                }
+            }
+        }
+
+        let (left, top, right, bottom) = bounds;
+        regions.push(DiffRect {
+            x: left,
+            y: top,
+            width: right - left + 1,
+            height: bottom - top + 1,
+        });
+    }
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    /// 在黑色背景上画出 `blobs` 中的白色矩形 (x, y, width, height)
+    fn frame(width: u32, height: u32, blobs: &[(u32, u32, u32, u32)]) -> RgbaImage {
+        let mut image = RgbaImage::from_pixel(width, height, BLACK);
+        for &(x, y, blob_width, blob_height) in blobs {
+            for py in y..y + blob_height {
+                for px in x..x + blob_width {
+                    image.put_pixel(px, py, WHITE);
+                }
+            }
+        }
+        image
+    }
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> DiffRect {
+        DiffRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    fn regions(before: &RgbaImage, after: &RgbaImage, cell: u32) -> Vec<DiffRect> {
+        changed_regions(&change_mask(before, after), cell)
+    }
+
+    #[test]
+    fn identical_frames_have_no_changes() {
+        let image = frame(40, 30, &[(5, 5, 10, 10)]);
+        let stats = compare(&image, &image.clone());
+        assert_eq!(stats.changed_pixels, 0);
+        assert_eq!(stats.total_pixels, 1200);
+        assert_eq!(stats.mean_error, 0.0);
+        assert_eq!(stats.changed_ratio(), 0.0);
+        assert!(change_mask(&image, &image)
+            .pixels()
+            .all(|pixel| pixel.0[0] == 0));
+        assert!(regions(&image, &image, 8).is_empty());
+    }
+
+    #[test]
+    fn compare_counts_changed_pixels() {
+        let before = frame(10, 10, &[]);
+        let after = frame(10, 10, &[(0, 0, 5, 2)]);
+        let stats = compare(&before, &after);
+        assert_eq!(stats.changed_pixels, 10);
+        assert_eq!(stats.changed_ratio(), 0.1);
+        // 每个变化像素 RGB 三个通道各差 255，透明度不变
+        assert_eq!(stats.mean_error, 10.0 * 255.0 * 3.0 / 400.0);
+    }
+
+    #[test]
+    fn mismatched_sizes_are_a_full_change() {
+        let before = frame(10, 10, &[]);
+        let after = frame(20, 5, &[]);
+        let stats = compare(&before, &after);
+        assert_eq!(stats.changed_pixels, 100);
+        assert_eq!(stats.total_pixels, 100);
+        assert_eq!(stats.changed_ratio(), 1.0);
+        assert_eq!(stats.mean_error, 255.0);
+
+        let mask = change_mask(&before, &after);
+        assert_eq!(mask.dimensions(), (20, 5));
+        assert!(mask.pixels().all(|pixel| pixel.0[0] == 255));
+        assert_eq!(changed_regions(&mask, 8), vec![rect(0, 0, 20, 5)]);
+    }
+
+    #[test]
+    fn empty_frames_have_no_changes() {
+        let image = RgbaImage::new(0, 0);
+        assert_eq!(compare(&image, &image).changed_ratio(), 0.0);
+        assert!(regions(&image, &image, 8).is_empty());
+    }
+
+    #[test]
+    fn separate_blobs_give_separate_rects() {
+        let before = frame(64, 64, &[]);
+        let after = frame(64, 64, &[(2, 3, 4, 5), (40, 50, 10, 6)]);
+        assert_eq!(
+            regions(&before, &after, 8),
+            vec![rect(2, 3, 4, 5), rect(40, 50, 10, 6)]
+        );
+    }
+
+    #[test]
+    fn rects_are_tight_around_changed_pixels() {
+        // 变化的像素跨越多个网格时，矩形仍然只包住变化的像素
+        let before = frame(64, 64, &[]);
+        let after = frame(64, 64, &[(5, 6, 20, 3)]);
+        assert_eq!(regions(&before, &after, 8), vec![rect(5, 6, 20, 3)]);
+    }
+
+    #[test]
+    fn blobs_in_adjacent_cells_merge() {
+        let before = frame(64, 64, &[]);
+        // 两块分别在第 0 列和第 1 列的网格中，网格相邻但像素不相邻
+        let after = frame(64, 64, &[(1, 1, 2, 2), (13, 1, 2, 2)]);
+        assert_eq!(regions(&before, &after, 8), vec![rect(1, 1, 14, 2)]);
+    }
+
+    #[test]
+    fn blobs_touching_at_cell_corners_merge() {
+        let before = frame(64, 64, &[]);
+        // 左上网格的右下角像素与右下网格的左上角像素对角相接
+        let after = frame(64, 64, &[(7, 7, 1, 1), (8, 8, 1, 1)]);
+        assert_eq!(regions(&before, &after, 8), vec![rect(7, 7, 2, 2)]);
+
+        // 只有网格对角相接，像素相距较远时同样合并
+        let after = frame(64, 64, &[(0, 0, 2, 2), (14, 14, 2, 2)]);
+        assert_eq!(regions(&before, &after, 8), vec![rect(0, 0, 16, 16)]);
+
+        // 另一条对角线
+        let after = frame(64, 64, &[(8, 0, 1, 1), (7, 8, 1, 1)]);
+        assert_eq!(regions(&before, &after, 8), vec![rect(7, 0, 2, 9)]);
+    }
+
+    #[test]
+    fn blobs_one_cell_apart_stay_separate() {
+        let before = frame(64, 64, &[]);
+        let after = frame(64, 64, &[(7, 7, 1, 1), (16, 16, 1, 1)]);
+        assert_eq!(
+            regions(&before, &after, 8),
+            vec![rect(7, 7, 1, 1), rect(16, 16, 1, 1)]
+        );
+    }
+
+    #[test]
+    fn zero_cell_is_treated_as_one_pixel() {
+        let before = frame(8, 8, &[]);
+        let after = frame(8, 8, &[(1, 1, 1, 1), (3, 1, 1, 1), (4, 2, 1, 1)]);
+        assert_eq!(
+            regions(&before, &after, 0),
+            vec![rect(1, 1, 1, 1), rect(3, 1, 2, 2)]
+        );
+    }
+}
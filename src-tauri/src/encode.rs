@@ -0,0 +1,267 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageFormat, ImageResult, RgbaImage};
+use serde::Deserialize;
+use std::io::Cursor;
+
+const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+/// 截图的输出格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    #[serde(alias = "jpg")]
+    Jpeg,
+    /// 无损 WebP，image crate 目前只支持无损编码
+    Webp,
+    Bmp,
+    Qoi,
+    /// 不经编码的 RGBA 像素，宽高通过响应头返回
+    Rgba,
+}
+
+impl OutputFormat {
+    pub fn content_type(self) -> &'static str {
+        match self.image_format() {
+            Some(format) => format.to_mime_type(),
+            None => "application/octet-stream",
+        }
+    }
+
+    fn image_format(self) -> Option<ImageFormat> {
+        match self {
+            OutputFormat::Png => Some(ImageFormat::Png),
+            OutputFormat::Jpeg => Some(ImageFormat::Jpeg),
+            OutputFormat::Webp => Some(ImageFormat::WebP),
+            OutputFormat::Bmp => Some(ImageFormat::Bmp),
+            OutputFormat::Qoi => Some(ImageFormat::Qoi),
+            OutputFormat::Rgba => None,
+        }
+    }
+
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type.to_ascii_lowercase().as_str() {
+            "image/png" => Some(OutputFormat::Png),
+            "image/jpeg" | "image/jpg" => Some(OutputFormat::Jpeg),
+            "image/webp" => Some(OutputFormat::Webp),
+            "image/bmp" => Some(OutputFormat::Bmp),
+            "image/qoi" | "image/x-qoi" => Some(OutputFormat::Qoi),
+            _ => None,
+        }
+    }
+
+    /// 按 `Accept` 请求头选择格式：默认的 PNG 仍然可以接受时，只有 q 值严格高于 PNG 的类型才会
+    /// 替换它，因此浏览器常见的 `image/webp,image/*,*/*` 仍然得到 PNG；q 值相同时取先出现的。
+    /// 返回 `None` 时由调用方使用默认格式
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        let media_ranges: Vec<(String, f32)> = accept
+            .split(',')
+            .map(|media_range| {
+                let mut parts = media_range.split(';');
+                let media_type = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+                let quality = parts
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (media_type, quality)
+            })
+            .collect();
+
+        // PNG 的 q 值取最具体的匹配：`image/png`，其次 `image/*`，最后 `*/*`
+        let png_quality = ["image/png", "image/*", "*/*"]
+            .iter()
+            .find_map(|media_type| {
+                media_ranges
+                    .iter()
+                    .find(|(range, _)| range == media_type)
+                    .map(|(_, quality)| *quality)
+            })
+            .unwrap_or(0.0);
+
+        let mut best: Option<(f32, OutputFormat)> = None;
+        for (media_type, quality) in &media_ranges {
+            let format = match OutputFormat::from_media_type(media_type) {
+                Some(OutputFormat::Png) | None => continue,
+                Some(format) => format,
+            };
+            if *quality > png_quality
+                && best.is_none_or(|(best_quality, _)| *quality > best_quality)
+            {
+                best = Some((*quality, format));
+            }
+        }
+        best.map(|(_, format)| format)
+    }
+}
+
+/// 编码参数，`format` 省略时由 `Accept` 请求头决定，默认 PNG
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct EncodeOptions {
+    pub format: Option<OutputFormat>,
+    /// JPEG 质量，1 到 100
+    pub quality: Option<u8>,
+    /// 格式由 `Accept` 请求头协商，响应需要带上 `Vary: Accept`
+    #[serde(skip)]
+    pub negotiated: bool,
+}
+
+impl EncodeOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.quality {
+            Some(quality) if !(1..=100).contains(&quality) => Err(format!(
+                "quality must be between 1 and 100, got {}",
+                quality
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// 查询参数没有指定 `format` 时使用 `Accept` 请求头协商的格式
+    pub fn negotiate(self, accept: Option<&str>) -> Self {
+        EncodeOptions {
+            format: self
+                .format
+                .or_else(|| accept.and_then(OutputFormat::from_accept)),
+            negotiated: self.format.is_none(),
+            ..self
+        }
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        self.format.unwrap_or_default()
+    }
+}
+
+pub fn encode_png(image: &RgbaImage) -> ImageResult<Vec<u8>> {
+    encode_image(image, &EncodeOptions::default())
+}
+
+pub fn encode_image(image: &RgbaImage, options: &EncodeOptions) -> ImageResult<Vec<u8>> {
+    let mut cursor = Cursor::new(Vec::new());
+    match options.format() {
+        OutputFormat::Rgba => return Ok(image.as_raw().clone()),
+        // JPEG 不支持透明通道，先转换为 RGB
+        OutputFormat::Jpeg => {
+            let rgb = DynamicImage::ImageRgba8(image.clone()).into_rgb8();
+            let quality = options.quality.unwrap_or(DEFAULT_JPEG_QUALITY);
+            JpegEncoder::new_with_quality(&mut cursor, quality).encode_image(&rgb)?;
+        }
+        format => {
+            let image_format = format.image_format().unwrap_or(ImageFormat::Png);
+            image.write_to(&mut cursor, image_format)?;
+        }
+    }
+    Ok(cursor.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn browser_accept_header_keeps_png() {
+        let chrome = "image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8";
+        assert_eq!(OutputFormat::from_accept(chrome), None);
+        let firefox = "image/avif,image/webp,image/png,image/svg+xml,image/*;q=0.8,*/*;q=0.5";
+        assert_eq!(OutputFormat::from_accept(firefox), None);
+        assert_eq!(OutputFormat::from_accept("*/*"), None);
+        assert_eq!(OutputFormat::from_accept(""), None);
+    }
+
+    #[test]
+    fn picks_formats_ranked_above_png() {
+        assert_eq!(
+            OutputFormat::from_accept("image/png;q=0.5, image/jpeg"),
+            Some(OutputFormat::Jpeg)
+        );
+        assert_eq!(
+            OutputFormat::from_accept("image/webp, image/*;q=0.8"),
+            Some(OutputFormat::Webp)
+        );
+        assert_eq!(
+            OutputFormat::from_accept("image/webp;q=0.9, */*;q=0.1"),
+            Some(OutputFormat::Webp)
+        );
+        // `image/png` 比 `image/*` 更具体
+        assert_eq!(
+            OutputFormat::from_accept("image/*, image/png;q=0.5, image/jpeg;q=0.6"),
+            Some(OutputFormat::Jpeg)
+        );
+    }
+
+    #[test]
+    fn png_not_accepted_picks_the_highest_quality() {
+        assert_eq!(
+            OutputFormat::from_accept("image/jpeg;q=0.5, image/webp;q=0.7"),
+            Some(OutputFormat::Webp)
+        );
+        assert_eq!(
+            OutputFormat::from_accept("IMAGE/JPEG"),
+            Some(OutputFormat::Jpeg)
+        );
+    }
+
+    #[test]
+    fn ties_keep_png_or_the_first_listed_format() {
+        assert_eq!(
+            OutputFormat::from_accept("image/webp;q=0.8, image/png;q=0.8"),
+            None
+        );
+        assert_eq!(
+            OutputFormat::from_accept("image/webp;q=0.8, image/jpeg;q=0.8"),
+            Some(OutputFormat::Webp)
+        );
+        assert_eq!(
+            OutputFormat::from_accept("image/jpeg;q=0.8, image/webp;q=0.8"),
+            Some(OutputFormat::Jpeg)
+        );
+    }
+
+    #[test]
+    fn zero_quality_is_not_acceptable() {
+        assert_eq!(OutputFormat::from_accept("image/jpeg;q=0"), None);
+        assert_eq!(
+            OutputFormat::from_accept("image/png;q=0, image/jpeg;q=0, image/webp;q=0.1"),
+            Some(OutputFormat::Webp)
+        );
+    }
+
+    #[test]
+    fn invalid_quality_defaults_to_one() {
+        assert_eq!(
+            OutputFormat::from_accept("image/png;q=0.9, image/jpeg;q=abc"),
+            Some(OutputFormat::Jpeg)
+        );
+    }
+
+    #[test]
+    fn query_format_overrides_accept() {
+        let query = EncodeOptions {
+            format: Some(OutputFormat::Png),
+            ..Default::default()
+        };
+        let options = query.negotiate(Some("image/jpeg"));
+        assert_eq!(options.format(), OutputFormat::Png);
+        assert!(!options.negotiated);
+
+        let options = EncodeOptions::default().negotiate(Some("image/jpeg"));
+        assert_eq!(options.format(), OutputFormat::Jpeg);
+        assert!(options.negotiated);
+
+        let options = EncodeOptions::default().negotiate(None);
+        assert_eq!(options.format(), OutputFormat::Png);
+        assert!(options.negotiated);
+    }
+}
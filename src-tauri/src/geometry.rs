@@ -0,0 +1,320 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+//! 屏幕坐标计算，不依赖窗口或截图 API，只处理数值
+//!
+//! 虚拟桌面坐标以主显示器左上角为原点，位于主显示器左侧或上方的显示器坐标为负数。
+
+// 目前只有 Windows 的客户区尺寸需要按显示器缩放换算
+#![cfg_attr(not(target_os = "windows"), allow(dead_code))]
+
+use serde::{Deserialize, Serialize};
+
+/// 屏幕上的一块矩形区域，单位为物理像素
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScreenRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ScreenRect {
+    /// 右边界（不含），使用 `i64` 避免 `width` 超过 `i32::MAX` 时溢出
+    pub fn right(&self) -> i64 {
+        self.x as i64 + self.width as i64
+    }
+
+    /// 下边界（不含）
+    pub fn bottom(&self) -> i64 {
+        self.y as i64 + self.height as i64
+    }
+
+    pub fn contains_rect(&self, other: &ScreenRect) -> bool {
+        other.x >= self.x
+            && other.y >= self.y
+            && other.right() <= self.right()
+            && other.bottom() <= self.bottom()
+    }
+
+    pub fn contains_point(&self, x: i32, y: i32) -> bool {
+        x >= self.x && y >= self.y && (x as i64) < self.right() && (y as i64) < self.bottom()
+    }
+
+    /// 点 (x, y) 到矩形的距离的平方，点在矩形内时为 0
+    fn distance_squared(&self, x: i32, y: i32) -> i64 {
+        let dx = (self.x as i64 - x as i64)
+            .max(x as i64 - (self.right() - 1))
+            .max(0);
+        let dy = (self.y as i64 - y as i64)
+            .max(y as i64 - (self.bottom() - 1))
+            .max(0);
+        dx * dx + dy * dy
+    }
+}
+
+/// 显示器在虚拟桌面上的物理范围，以及它当前的缩放比例
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MonitorGeometry {
+    pub bounds: ScreenRect,
+    pub scale_factor: f64,
+}
+
+/// 包含点 (x, y) 的显示器；点不在任何显示器上时取距离最近的一个，
+/// 与 `MonitorFromPoint(MONITOR_DEFAULTTONEAREST)` 的行为一致
+pub fn monitor_at(monitors: &[MonitorGeometry], x: i32, y: i32) -> Option<&MonitorGeometry> {
+    monitors
+        .iter()
+        .min_by_key(|monitor| monitor.bounds.distance_squared(x, y))
+}
+
+/// 逻辑尺寸换算为物理尺寸，向上取整，保证截图覆盖整个区域
+pub fn logical_to_physical_size(width: f64, height: f64, scale_factor: f64) -> (u32, u32) {
+    let scale = |value: f64| (value * scale_factor).ceil().max(0.0) as u32;
+    (scale(width), scale(height))
+}
+
+/// 窗口客户区的物理范围
+///
+/// `x`、`y` 为客户区左上角在虚拟桌面上的物理坐标，`width`、`height` 为逻辑尺寸，
+/// 按左上角所在显示器当前的缩放比例换算；找不到显示器时按 1.0 处理。
+pub fn client_rect_to_physical(
+    monitors: &[MonitorGeometry],
+    x: i32,
+    y: i32,
+    width: f64,
+    height: f64,
+) -> ScreenRect {
+    let scale_factor = monitor_scale_factor(monitors, x, y);
+    let (width, height) = logical_to_physical_size(width, height, scale_factor);
+    ScreenRect {
+        x,
+        y,
+        width,
+        height,
+    }
+}
+
+/// 点 (x, y) 所在显示器的缩放比例，找不到显示器时为 1.0
+fn monitor_scale_factor(monitors: &[MonitorGeometry], x: i32, y: i32) -> f64 {
+    monitor_at(monitors, x, y)
+        .map(|monitor| monitor.scale_factor)
+        .filter(|scale_factor| *scale_factor > 0.0)
+        .unwrap_or(1.0)
+}
+
+/// 窗口按物理尺寸 `expected` 设置过大小时，`rect` 与它只差逻辑尺寸取整的误差则使用 `expected`
+///
+/// 例如 1.5 倍缩放下设置为 1024 的窗口，逻辑宽度为 683，换算回来是 1025。
+/// 窗口之后被拖动改变了大小时误差超出范围，仍使用 `rect` 的尺寸。
+pub fn restore_physical_size(
+    monitors: &[MonitorGeometry],
+    rect: ScreenRect,
+    expected: (u32, u32),
+) -> ScreenRect {
+    // 逻辑尺寸取整最多带来小于一个逻辑像素的误差，1.0 倍时没有误差
+    let tolerance =
+        (monitor_scale_factor(monitors, rect.x, rect.y).ceil() as u32).saturating_sub(1);
+    let (width, height) = expected;
+    if rect.width.abs_diff(width) <= tolerance && rect.height.abs_diff(height) <= tolerance {
+        ScreenRect {
+            width,
+            height,
+            ..rect
+        }
+    } else {
+        rect
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> ScreenRect {
+        ScreenRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// 主显示器 1.5 倍，左侧副显示器 1.0 倍，上方副显示器 2.0 倍
+    fn monitors() -> Vec<MonitorGeometry> {
+        vec![
+            MonitorGeometry {
+                bounds: rect(0, 0, 2560, 1440),
+                scale_factor: 1.5,
+            },
+            MonitorGeometry {
+                bounds: rect(-1920, 0, 1920, 1080),
+                scale_factor: 1.0,
+            },
+            MonitorGeometry {
+                bounds: rect(0, -2160, 3840, 2160),
+                scale_factor: 2.0,
+            },
+        ]
+    }
+
+    fn scale_at(x: i32, y: i32) -> f64 {
+        monitor_at(&monitors(), x, y).unwrap().scale_factor
+    }
+
+    #[test]
+    fn contains_point_includes_left_top_edges_only() {
+        let left = rect(-1920, 0, 1920, 1080);
+        assert!(left.contains_point(-1920, 0));
+        assert!(left.contains_point(-1, 1079));
+        assert!(!left.contains_point(0, 0));
+        assert!(!left.contains_point(-1, 1080));
+        assert!(!left.contains_point(-1921, 0));
+    }
+
+    #[test]
+    fn contains_rect_allows_rects_ending_on_the_border() {
+        let left = rect(-1920, 0, 1920, 1080);
+        assert!(left.contains_rect(&left));
+        assert!(left.contains_rect(&rect(-100, 980, 100, 100)));
+        assert!(!left.contains_rect(&rect(-100, 980, 101, 100)));
+        assert!(!left.contains_rect(&rect(-1921, 0, 10, 10)));
+        assert!(!left.contains_rect(&rect(-1, 0, 2, 10)));
+    }
+
+    #[test]
+    fn edges_do_not_overflow() {
+        let huge = rect(i32::MAX, i32::MIN, u32::MAX, u32::MAX);
+        assert_eq!(huge.right(), i32::MAX as i64 + u32::MAX as i64);
+        assert_eq!(huge.bottom(), i32::MIN as i64 + u32::MAX as i64);
+        assert!(!rect(0, 0, 2560, 1440).contains_rect(&rect(1, 1, u32::MAX, 1)));
+        assert!(rect(i32::MIN, 0, u32::MAX, 1).contains_point(i32::MAX - 1, 0));
+    }
+
+    #[test]
+    fn monitor_at_finds_the_containing_monitor() {
+        assert_eq!(scale_at(0, 0), 1.5);
+        assert_eq!(scale_at(2559, 1439), 1.5);
+        assert_eq!(scale_at(-1920, 0), 1.0);
+        assert_eq!(scale_at(-1, 1079), 1.0);
+        assert_eq!(scale_at(0, -1), 2.0);
+        assert_eq!(scale_at(3839, -2160), 2.0);
+    }
+
+    #[test]
+    fn monitor_at_border_belongs_to_the_monitor_starting_there() {
+        // x = 0 是左侧显示器的右边界（不含），属于主显示器
+        assert_eq!(scale_at(0, 500), 1.5);
+        // y = 0 是上方显示器的下边界（不含），属于主显示器
+        assert_eq!(scale_at(100, 0), 1.5);
+    }
+
+    #[test]
+    fn monitor_at_falls_back_to_the_nearest_monitor() {
+        // 左侧显示器下方的空白处，离主显示器更近
+        assert_eq!(scale_at(-100, 1300), 1.5);
+        // 离左侧显示器的下边缘更近
+        assert_eq!(scale_at(-1800, 1090), 1.0);
+        // 主显示器右上方，离上方显示器更近
+        assert_eq!(scale_at(5000, -3000), 2.0);
+        assert!(monitor_at(&[], 0, 0).is_none());
+    }
+
+    #[test]
+    fn logical_to_physical_size_rounds_up() {
+        assert_eq!(logical_to_physical_size(100.0, 50.0, 1.0), (100, 50));
+        assert_eq!(logical_to_physical_size(100.0, 50.0, 1.5), (150, 75));
+        assert_eq!(logical_to_physical_size(101.0, 51.0, 1.5), (152, 77));
+        assert_eq!(logical_to_physical_size(100.0, 50.0, 2.0), (200, 100));
+        assert_eq!(logical_to_physical_size(-10.0, 0.0, 2.0), (0, 0));
+    }
+
+    #[test]
+    fn client_rect_uses_the_scale_of_its_origin_monitor() {
+        let monitors = monitors();
+        assert_eq!(
+            client_rect_to_physical(&monitors, -1000, 100, 640.0, 480.0),
+            rect(-1000, 100, 640, 480)
+        );
+        assert_eq!(
+            client_rect_to_physical(&monitors, 100, 100, 640.0, 480.0),
+            rect(100, 100, 960, 720)
+        );
+        assert_eq!(
+            client_rect_to_physical(&monitors, 100, -1000, 640.0, 480.0),
+            rect(100, -1000, 1280, 960)
+        );
+        // 跨过边界的窗口按左上角所在的显示器换算
+        assert_eq!(
+            client_rect_to_physical(&monitors, -10, 100, 640.0, 480.0),
+            rect(-10, 100, 640, 480)
+        );
+        assert_eq!(
+            client_rect_to_physical(&monitors, 0, 0, 640.0, 480.0),
+            rect(0, 0, 960, 720)
+        );
+    }
+
+    #[test]
+    fn restores_sizes_within_rounding_error() {
+        let monitors = monitors();
+        // 1.5 倍：1024 -> 逻辑 683 -> 1025
+        let captured = client_rect_to_physical(&monitors, 100, 100, 683.0, 683.0);
+        assert_eq!(captured, rect(100, 100, 1025, 1025));
+        assert_eq!(
+            restore_physical_size(&monitors, captured, (1024, 1024)),
+            rect(100, 100, 1024, 1024)
+        );
+        // 2.0 倍下奇数尺寸的误差为 1 像素
+        let captured = rect(100, -1000, 1217, 833);
+        assert_eq!(
+            restore_physical_size(&monitors, captured, (1216, 832)),
+            rect(100, -1000, 1216, 832)
+        );
+    }
+
+    #[test]
+    fn keeps_sizes_changed_after_snapping() {
+        let monitors = monitors();
+        let captured = rect(100, 100, 1030, 1024);
+        assert_eq!(
+            restore_physical_size(&monitors, captured, (1024, 1024)),
+            captured
+        );
+        let captured = rect(100, -1000, 1218, 832);
+        assert_eq!(
+            restore_physical_size(&monitors, captured, (1216, 832)),
+            captured
+        );
+        // 1.0 倍下没有取整误差
+        let captured = rect(-1000, 100, 1025, 1024);
+        assert_eq!(
+            restore_physical_size(&monitors, captured, (1024, 1024)),
+            captured
+        );
+    }
+
+    #[test]
+    fn client_rect_without_monitor_uses_scale_one() {
+        assert_eq!(
+            client_rect_to_physical(&[], -50, -50, 640.0, 480.0),
+            rect(-50, -50, 640, 480)
+        );
+        let unknown_scale = [MonitorGeometry {
+            bounds: rect(0, 0, 1920, 1080),
+            scale_factor: 0.0,
+        }];
+        assert_eq!(
+            client_rect_to_physical(&unknown_scale, 10, 10, 640.0, 480.0),
+            rect(10, 10, 640, 480)
+        );
+    }
+}
@@ -14,39 +14,46 @@
     windows_subsystem = "windows"
 )]
 
+mod capture;
+mod config;
+mod diff;
+mod encode;
+mod geometry;
+mod metrics;
+mod regions;
+mod resize;
+mod server;
+mod settle;
+#[cfg(target_os = "windows")]
 mod windows_utils;
 
 use base64::encode;
-use image::ImageFormat;
-use std::io::Cursor;
-use std::sync::atomic::{AtomicBool, Ordering};
+use capture::app_window::{capture_window, list_windows, WindowInfo, WindowSelector};
+use capture::monitor::{self, MonitorInfo};
+use capture::{backend_from_config, capture_screen, CaptureOptions, SharedCaptureBackend};
+use diff::content_hash;
+use encode::{encode_image, encode_png, EncodeOptions, OutputFormat};
+use geometry::logical_to_physical_size;
+use image::RgbaImage;
+use metrics::Metrics;
+use regions::Regions;
+use resize::{resize_image, Fit, ResizeFilter, ResizeOptions, Snap};
+use settle::{SettleOptions, Settler};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use tauri::Listener;
 use tauri::{generate_handler, AppHandle, Emitter, EventLoopMessage, Manager, State, Window, Wry};
 use tauri::{
-    menu::{Menu, MenuItem, MenuItemKind, PredefinedMenuItem},
+    menu::{Menu, MenuItem, MenuItemKind, PredefinedMenuItem, Submenu},
     tray::TrayIconBuilder,
+    WindowEvent,
 };
 use tokio::sync::oneshot;
-use warp::http::Response;
-use warp::hyper::Body;
-use warp::{reject::Reject, Filter};
-use windows_utils::capture::capture_screen;
+use xcap::{XCapError, XCapResult};
 // use tauri_plugin_clipboard_manager;
 
-#[derive(Debug)]
-struct CustomError {
-    message: String,
-}
-
-impl Reject for CustomError {}
-
-impl CustomError {
-    fn ImageWriteError(message: String) -> Self {
-        CustomError { message }
-    }
-}
-
 struct RecorderState {
     http_server: Mutex<Option<tokio::task::JoinHandle<()>>>,
     stop_signal: Mutex<Option<oneshot::Sender<()>>>,
@@ -54,6 +61,28 @@ struct RecorderState {
     last_port: Mutex<Option<u16>>,
     is_pin: AtomicBool,
     tray_menu: Mutex<Option<Menu<tauri::Wry>>>, // 添加这个字段
+    capture_backend: SharedCaptureBackend,
+    regions: Regions,
+    /// HTTP 截图的序号，每次成功截图加一
+    capture_sequence: AtomicU64,
+    /// HTTP 截图最近一次的耗时与错误
+    capture_status: Mutex<server::CaptureStatus>,
+    started_at: Instant,
+    metrics: Metrics,
+    /// HTTP API 的令牌，第一次使用时读取或生成，见 `server::api_token`
+    api_token: Mutex<Option<String>>,
+    /// HTTP 请求是否需要令牌
+    require_token: AtomicBool,
+    /// `resize_window` 按 `snap` 设置的物理尺寸，按窗口 label 保存
+    snapped_sizes: Mutex<HashMap<String, (u32, u32)>>,
+    /// 所有截图共用，同一时间只进行一次截图
+    capturer: Arc<server::Capturer>,
+}
+
+impl RecorderState {
+    fn capture_backend(&self) -> SharedCaptureBackend {
+        self.capture_backend.clone()
+    }
 }
 
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
@@ -63,15 +92,41 @@ fn greet(message: &str) -> String {
     format!("Hello, {}! You've been greeted from Rust!", message)
 }
 
+/// `snap` 不为空时按当前显示器的缩放比例换算出物理尺寸，
+/// 取整到最接近的合法尺寸后再设置，截图尺寸与之一致
 #[tauri::command]
-fn resize_window(window: Window, width: f64, height: f64) -> Result<(), String> {
+fn resize_window(
+    window: Window,
+    width: f64,
+    height: f64,
+    snap: Option<Snap>,
+) -> Result<(), String> {
     // println!(
     //     "resize_window invoked with width: {}, height: {}",
     //     width, height
     // );
-    window
-        .set_size(tauri::Size::Logical(tauri::LogicalSize { width, height }))
-        .map_err(|e| e.to_string())
+    let snapped_size = match snap {
+        Some(snap) => {
+            let scale_factor = window.scale_factor().map_err(|e| e.to_string())?;
+            let (width, height) = logical_to_physical_size(width, height, scale_factor);
+            Some(snap.nearest_size(width, height))
+        }
+        None => None,
+    };
+    let size = match snapped_size {
+        Some((width, height)) => tauri::Size::Physical(tauri::PhysicalSize { width, height }),
+        None => tauri::Size::Logical(tauri::LogicalSize { width, height }),
+    };
+    window.set_size(size).map_err(|e| e.to_string())?;
+
+    // 截图时据此消除逻辑尺寸换算带来的取整误差，见 `capture::overlay_screen_rect`
+    let state = window.state::<RecorderState>();
+    let mut snapped_sizes = state.snapped_sizes.lock().unwrap();
+    match snapped_size {
+        Some(size) => snapped_sizes.insert(window.label().to_string(), size),
+        None => snapped_sizes.remove(window.label()),
+    };
+    Ok(())
 }
 
 #[tauri::command]
@@ -79,31 +134,163 @@ fn set_window_title(window: Window, title: String) {
     window.set_title(&title).unwrap();
 }
 
+// async command 在 tokio 线程上执行，隐藏窗口时不会阻塞主线程的事件循环
 #[tauri::command]
-fn capture_window_screenshot(window: Window, scale_factor: f32) -> Result<String, String> {
+#[allow(clippy::too_many_arguments)]
+async fn capture_window_screenshot(
+    state: State<'_, RecorderState>,
+    window: Window,
+    include_cursor: Option<bool>,
+    width: Option<u32>,
+    height: Option<u32>,
+    scale: Option<f64>,
+    fit: Option<Fit>,
+    filter: Option<ResizeFilter>,
+    format: Option<OutputFormat>,
+    quality: Option<u8>,
+    settle_ms: Option<u64>,
+    max_wait_ms: Option<u64>,
+) -> Result<String, String> {
     println!("capture_window_screenshot");
-    let tauri_window_hwnd = window.hwnd().unwrap_or_default();
-    // let tauri_window_pid = get_window_pid(tauri_window_hwnd);
+    let backend = state.capture_backend();
+    let options = CaptureOptions {
+        include_cursor: include_cursor.unwrap_or(false),
+    };
+    let resize = ResizeOptions {
+        width,
+        height,
+        scale,
+        fit,
+        filter,
+        ..Default::default()
+    };
+    let encode_options = EncodeOptions {
+        format,
+        quality,
+        ..Default::default()
+    };
+    let settle = settle_ms.map(|settle_ms| SettleOptions {
+        settle_ms,
+        max_wait_ms,
+    });
+    resize.validate()?;
+    encode_options.validate()?;
+    if let Some(settle) = &settle {
+        settle.validate()?;
+    }
 
-    match capture_screen(tauri_window_hwnd, scale_factor) {
+    let capturer = &state.capturer;
+    let result = match &settle {
+        Some(settle) => {
+            capture_screen_settled(capturer, &backend, &window, options, settle).await
+        }
+        None => capture_screen_timed(capturer, &backend, &window, options).await,
+    };
+    let metrics = &state.metrics;
+    match result {
         Ok(image) => {
-            let mut cursor = Cursor::new(Vec::new());
-            image
-                .write_to(&mut cursor, ImageFormat::Png)
-                .map_err(|e| e.to_string())?;
-            let encoded_image = encode(cursor.into_inner());
+            let started = Instant::now();
+            let image = resize_image(image, &resize)?;
+            let encoded_image = encode(
+                encode_image(&image, &encode_options).map_err(|e| e.to_string())?,
+            );
+            metrics.observe_encode(started.elapsed());
             Ok(encoded_image)
         }
         Err(_) => Err("Screenshot capture failed".into()),
     }
 }
 
+/// 反复截图，返回第一帧连续 `settle_ms` 没有变化的截图，超过 `max_wait_ms` 时返回最后一帧
+async fn capture_screen_settled(
+    capturer: &server::Capturer,
+    backend: &SharedCaptureBackend,
+    window: &Window,
+    options: CaptureOptions,
+    settle: &SettleOptions,
+) -> XCapResult<RgbaImage> {
+    let mut settler = Settler::new(settle);
+    loop {
+        let image = capture_screen_timed(capturer, backend, window, options).await?;
+        if settler.sample(content_hash(image.as_raw())) {
+            return Ok(image);
+        }
+        tokio::time::sleep(settler.interval()).await;
+    }
+}
+
+/// 截取覆盖窗口下方的屏幕并记录耗时，与 HTTP 接口的截图错开进行
+async fn capture_screen_timed(
+    capturer: &server::Capturer,
+    backend: &SharedCaptureBackend,
+    window: &Window,
+    options: CaptureOptions,
+) -> XCapResult<RgbaImage> {
+    let backend = backend.clone();
+    let window = window.clone();
+    capturer
+        .exclusive(move || {
+            let metrics = &window.state::<RecorderState>().metrics;
+            let started = Instant::now();
+            let result = capture_screen(&*backend, &window, options);
+            match &result {
+                Ok(_) => metrics.observe_capture(started.elapsed()),
+                Err(_) => metrics.capture_failed(),
+            }
+            result
+        })
+        .await
+        .unwrap_or_else(|message| Err(XCapError::new(message)))
+}
+
+/// 所有显示器的位置、大小和缩放比例
+#[tauri::command]
+fn list_monitors() -> Result<Vec<MonitorInfo>, String> {
+    monitor::list_monitors().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn list_app_windows() -> Result<Vec<WindowInfo>, String> {
+    list_windows().map_err(|e| e.to_string())
+}
+
+/// 截取按标题、进程名或 id 指定的应用窗口，返回 base64 编码的 PNG
+#[tauri::command]
+async fn capture_app_window_screenshot(
+    state: State<'_, RecorderState>,
+    title: Option<String>,
+    app: Option<String>,
+    pid: Option<u32>,
+    id: Option<u32>,
+) -> Result<String, String> {
+    let selector = WindowSelector {
+        title,
+        app,
+        pid,
+        id,
+    };
+    if selector.is_empty() {
+        return Err("One of title, app, pid or id is required".into());
+    }
+
+    // 枚举和截取窗口会阻塞，与其他截图一样在阻塞线程中依次进行
+    let image = state
+        .capturer
+        .exclusive(move || capture_window(&selector))
+        .await?
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Window not found".to_string())?;
+    tokio::task::spawn_blocking(move || encode_png(&image).map(encode))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 fn restart_http_server(
     state: State<'_, RecorderState>,
-    window: Window,
+    app_handle: AppHandle,
     port: u16,
-    scale_factor: f32,
 ) {
     // 检查传入的端口号是否与上一次相同
     {
@@ -141,48 +328,7 @@ fn restart_http_server(
     }
 
     let handle = state.tokio_runtime.spawn(async move {
-        let is_capturing = Arc::new(Mutex::new(false));
-        let capture_route = warp::path("capture_screen")
-            .and(with_capturing(is_capturing.clone()))
-            .and_then(move |_| {
-                let window = window.clone();
-                async move {
-                    let tauri_window_hwnd = window.hwnd().unwrap_or_default();
-                    let result = match capture_screen(tauri_window_hwnd, scale_factor) {
-                        Ok(image) => {
-                            let mut cursor = Cursor::new(Vec::new());
-                            image.write_to(&mut cursor, ImageFormat::Png).map_err(|e| {
-                                warp::reject::custom(CustomError::ImageWriteError(e.to_string()))
-                            })?;
-                            let binary_image = cursor.into_inner();
-                            Ok::<_, warp::Rejection>(
-                                Response::builder()
-                                    .header("Content-Type", "image/png")
-                                    .body(Body::from(binary_image))
-                                    .unwrap(),
-                            )
-                        }
-                        Err(_) => Err(warp::reject::not_found()),
-                    };
-
-                    result
-                }
-            });
-
-        let default_route = warp::any().map(|| {
-            warp::reply::with_status("Not Found".to_string(), warp::http::StatusCode::NOT_FOUND)
-        });
-
-        let routes = capture_route
-            .or(default_route)
-            .with(warp::log("capture_screen"));
-
-        let (_addr, server) =
-            warp::serve(routes).bind_with_graceful_shutdown(([127, 0, 0, 1], port), async {
-                rx.await.ok();
-            });
-
-        server.await;
+        server::serve(app_handle, port, rx).await;
 
         println!("server stopped...");
     });
@@ -191,12 +337,6 @@ fn restart_http_server(
     *state.stop_signal.lock().unwrap() = Some(tx);
 }
 
-fn with_capturing(
-    is_capturing: Arc<Mutex<bool>>,
-) -> impl Filter<Extract = (Arc<Mutex<bool>>,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || is_capturing.clone())
-}
-
 #[tauri::command]
 fn stop_http_server(state: State<'_, RecorderState>) {
     let mut stop_signal = state.stop_signal.lock().unwrap();
@@ -233,6 +373,33 @@ fn stop_http_server(state: State<'_, RecorderState>) {
     }
 }
 
+/// 复制的 URL 中使用的主机名，与 `--bind` 指定的监听地址一致
+#[tauri::command]
+fn get_server_host() -> String {
+    server::url_host()
+}
+
+/// 需要令牌时返回令牌，复制的 URL 中会带上它；不需要时返回 `None`
+#[tauri::command]
+fn get_api_token(state: State<'_, RecorderState>, app_handle: AppHandle) -> Option<String> {
+    state
+        .require_token
+        .load(Ordering::SeqCst)
+        .then(|| server::api_token(&app_handle))
+}
+
+#[tauri::command]
+fn set_require_token(
+    state: State<'_, RecorderState>,
+    app_handle: AppHandle,
+    require_token: bool,
+) -> Option<String> {
+    // 对外监听且没有允许列表时不能关闭，返回的令牌表示仍然开启
+    let require_token = require_token || server::token_enforced();
+    state.require_token.store(require_token, Ordering::SeqCst);
+    get_api_token(state, app_handle)
+}
+
 #[tauri::command]
 fn get_is_server_running(state: State<'_, RecorderState>) -> bool {
     let http_server = state.http_server.lock().unwrap();
@@ -250,6 +417,11 @@ fn get_is_server_running(state: State<'_, RecorderState>) -> bool {
     is_running
 }
 
+#[tauri::command]
+fn get_server_port(state: State<'_, RecorderState>) -> Option<u16> {
+    *state.last_port.lock().unwrap()
+}
+
 #[tauri::command]
 fn get_is_pin(state: State<'_, RecorderState>) -> bool {
     state.is_pin.load(Ordering::SeqCst)
@@ -290,7 +462,11 @@ fn quit_app(app_handle: AppHandle) {
 }
 
 pub fn run() {
+    // 服务端和截图后端的日志通过 tracing 输出到标准输出
+    tracing_subscriber::fmt().init();
     let tokio_runtime = tokio::runtime::Runtime::new().unwrap();
+    let capture_backend = backend_from_config();
+    println!("capture backend: {}", capture_backend.name());
 
     tauri::Builder::default()
         .plugin(tauri_plugin_clipboard::init()) 
@@ -299,12 +475,18 @@ pub fn run() {
             let unpin_i = MenuItem::with_id(app, "unpin", "UnPin", false, None::<&str>)?;
             let copy_url_i =
                 MenuItem::with_id(app, "copy_screenshot_url", "Copy ScreenShoot Url", true, None::<&str>)?;
+            let new_region_i =
+                MenuItem::with_id(app, "new_region", "New Region", true, None::<&str>)?;
+            let regions_i = Submenu::with_id(app, "regions", "Regions", true)?;
 
             let menu = Menu::with_items(
                 app,
                 &[
                     &copy_url_i,
                     &PredefinedMenuItem::separator(app)?,
+                    &new_region_i,
+                    &regions_i,
+                    &PredefinedMenuItem::separator(app)?,
                     &pin_i,
                     &unpin_i,
                     &PredefinedMenuItem::separator(app)?,
@@ -317,6 +499,9 @@ pub fn run() {
                 .menu(&menu)
                 .show_menu_on_left_click(true)
                 .on_menu_event(|app_handle, event| {
+                    if regions::handle_menu_event(app_handle, event.id.as_ref()) {
+                        return;
+                    }
                     let state = app_handle.state::<RecorderState>();
                     let tray_menu = state.tray_menu.lock().unwrap();
                     if let Some(tray_menu) = &*tray_menu {
@@ -344,7 +529,9 @@ pub fn run() {
             let app_handle = app.handle().clone();
             app.listen("is_pin_changed", move |event| {
                 if let Some(is_pin) = event.payload().parse::<bool>().ok() {
-                    let window = app_handle.get_webview_window("main").unwrap();
+                    let window = app_handle
+                        .get_webview_window(regions::MAIN_WINDOW_LABEL)
+                        .unwrap();
                     if is_pin {
                         // window.set_decorations(false).unwrap(); // 隐藏顶部栏
                         window.set_shadow(false).unwrap();
@@ -374,6 +561,23 @@ pub fn run() {
             last_port: Mutex::new(None),
             is_pin: AtomicBool::new(false),
             tray_menu: Mutex::new(None), // 初始化tray_menu
+            capture_backend,
+            regions: Regions::default(),
+            capture_sequence: AtomicU64::new(0),
+            capture_status: Mutex::new(server::CaptureStatus::default()),
+            started_at: Instant::now(),
+            metrics: Metrics::default(),
+            api_token: Mutex::new(None),
+            require_token: AtomicBool::new(
+                server::configured_token().is_some() || server::token_enforced(),
+            ),
+            snapped_sizes: Mutex::new(HashMap::new()),
+            capturer: Arc::new(server::Capturer::from_config()),
+        })
+        .on_window_event(|window, event| {
+            if let WindowEvent::Destroyed = event {
+                regions::forget_window(window.app_handle(), window.label());
+            }
         })
         .plugin(tauri_plugin_opener::init())
         .invoke_handler(tauri::generate_handler![
@@ -381,13 +585,25 @@ pub fn run() {
             resize_window,
             set_window_title,
             capture_window_screenshot,
+            list_monitors,
+            list_app_windows,
+            capture_app_window_screenshot,
             restart_http_server,
             stop_http_server,
             get_is_server_running,
+            get_server_port,
+            get_server_host,
+            get_api_token,
+            set_require_token,
             get_is_pin,
             set_is_pin,
             start_dragging,
-            quit_app
+            quit_app,
+            regions::create_region,
+            regions::rename_region,
+            regions::close_region,
+            regions::list_regions,
+            regions::get_region_name
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,288 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+//! 截图、编码和 HTTP 请求的统计，以 Prometheus 文本格式输出
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// 直方图的桶上限，单位为秒，与 Prometheus 客户端的默认值相同
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+struct Histogram {
+    /// 每个桶单独的计数，输出时再累加
+    counts: [AtomicU64; BUCKETS.len()],
+    sum_nanos: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            counts: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum_nanos: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(index) = BUCKETS.iter().position(|bucket| seconds <= *bucket) {
+            self.counts[index].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        writeln!(out, "# HELP {} {}", name, help).unwrap();
+        writeln!(out, "# TYPE {} histogram", name).unwrap();
+        let mut cumulative = 0;
+        for (bucket, count) in BUCKETS.iter().zip(&self.counts) {
+            cumulative += count.load(Ordering::Relaxed);
+            writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bucket, cumulative).unwrap();
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count).unwrap();
+        let sum = self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        writeln!(out, "{}_sum {}", name, sum).unwrap();
+        writeln!(out, "{}_count {}", name, count).unwrap();
+    }
+}
+
+/// 保存在 `RecorderState` 中，包括应用内截图和 HTTP 截图
+pub struct Metrics {
+    capture_duration: Histogram,
+    encode_duration: Histogram,
+    capture_errors: AtomicU64,
+    /// 排队的截图过多而拒绝的请求
+    rejections: AtomicU64,
+    served_bytes: AtomicU64,
+    /// 按路由和状态码统计的请求数，路由只有固定的几种
+    requests: Mutex<BTreeMap<(&'static str, u16), u64>>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            capture_duration: Histogram::new(),
+            encode_duration: Histogram::new(),
+            capture_errors: AtomicU64::new(0),
+            rejections: AtomicU64::new(0),
+            served_bytes: AtomicU64::new(0),
+            requests: Mutex::new(BTreeMap::new()),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn observe_capture(&self, duration: Duration) {
+        self.capture_duration.observe(duration);
+    }
+
+    pub fn capture_failed(&self) {
+        self.capture_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn observe_encode(&self, duration: Duration) {
+        self.encode_duration.observe(duration);
+    }
+
+    pub fn add_served_bytes(&self, bytes: usize) {
+        self.served_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn rejected(&self) {
+        self.rejections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn observe_request(&self, route: &'static str, status: u16) {
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry((route, status))
+            .or_default() += 1;
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        self.capture_duration.render(
+            &mut out,
+            "comfy_capturer_capture_duration_seconds",
+            "Time spent capturing the screen.",
+        );
+        self.encode_duration.render(
+            &mut out,
+            "comfy_capturer_encode_duration_seconds",
+            "Time spent resizing and encoding captures.",
+        );
+        render_counter(
+            &mut out,
+            "comfy_capturer_capture_errors_total",
+            "Captures that failed.",
+            &self.capture_errors,
+        );
+        render_counter(
+            &mut out,
+            "comfy_capturer_rejections_total",
+            "Requests rejected because too many captures were pending.",
+            &self.rejections,
+        );
+        render_counter(
+            &mut out,
+            "comfy_capturer_served_bytes_total",
+            "Encoded image bytes sent over HTTP and WebSocket.",
+            &self.served_bytes,
+        );
+
+        let name = "comfy_capturer_http_requests_total";
+        writeln!(out, "# HELP {} HTTP requests by route and status.", name).unwrap();
+        writeln!(out, "# TYPE {} counter", name).unwrap();
+        for ((route, status), count) in self.requests.lock().unwrap().iter() {
+            writeln!(
+                out,
+                "{}{{route=\"{}\",status=\"{}\"}} {}",
+                name, route, status, count
+            )
+            .unwrap();
+        }
+        out
+    }
+}
+
+fn render_counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} counter", name).unwrap();
+    writeln!(out, "{} {}", name, value.load(Ordering::Relaxed)).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `render` 输出中名为 `name` 的样本的值
+    fn sample(out: &str, name: &str) -> u64 {
+        out.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("{} not found in\n{}", name, out))
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let metrics = Metrics::default();
+        for ms in [1, 3, 20, 300, 300, 20_000] {
+            metrics.observe_capture(Duration::from_millis(ms));
+        }
+        let out = metrics.render();
+        let name = "comfy_capturer_capture_duration_seconds";
+        let bucket = |le: &str| sample(&out, &format!("{}_bucket{{le=\"{}\"}}", name, le));
+
+        assert_eq!(bucket("0.005"), 2);
+        assert_eq!(bucket("0.01"), 2);
+        assert_eq!(bucket("0.025"), 3);
+        assert_eq!(bucket("0.25"), 3);
+        assert_eq!(bucket("0.5"), 5);
+        assert_eq!(bucket("10"), 5);
+        // 超过最大桶的观测只计入 `+Inf`
+        assert_eq!(bucket("+Inf"), 6);
+        assert_eq!(sample(&out, &format!("{}_count", name)), 6);
+
+        let buckets: Vec<u64> = BUCKETS.iter().map(|le| bucket(&le.to_string())).collect();
+        assert!(buckets.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn histogram_sum_is_in_seconds() {
+        let metrics = Metrics::default();
+        metrics.observe_encode(Duration::from_millis(250));
+        metrics.observe_encode(Duration::from_millis(1250));
+        let out = metrics.render();
+        assert!(out
+            .lines()
+            .any(|line| line == "comfy_capturer_encode_duration_seconds_sum 1.5"));
+    }
+
+    #[test]
+    fn empty_histogram_has_zero_counts() {
+        let out = Metrics::default().render();
+        let name = "comfy_capturer_encode_duration_seconds";
+        assert_eq!(sample(&out, &format!("{}_bucket{{le=\"+Inf\"}}", name)), 0);
+        assert_eq!(sample(&out, &format!("{}_count", name)), 0);
+        assert!(out.contains(&format!("{}_sum 0\n", name)));
+    }
+
+    #[test]
+    fn every_metric_has_help_and_type() {
+        let out = Metrics::default().render();
+        for (name, kind) in [
+            ("comfy_capturer_capture_duration_seconds", "histogram"),
+            ("comfy_capturer_encode_duration_seconds", "histogram"),
+            ("comfy_capturer_capture_errors_total", "counter"),
+            ("comfy_capturer_rejections_total", "counter"),
+            ("comfy_capturer_served_bytes_total", "counter"),
+            ("comfy_capturer_http_requests_total", "counter"),
+        ] {
+            let help = out
+                .lines()
+                .position(|line| line.starts_with(&format!("# HELP {} ", name)))
+                .unwrap_or_else(|| panic!("missing HELP for {}", name));
+            let type_line = format!("# TYPE {} {}", name, kind);
+            assert_eq!(out.lines().nth(help + 1), Some(type_line.as_str()));
+        }
+        assert!(out.ends_with('\n'));
+    }
+
+    #[test]
+    fn counters_render_their_values() {
+        let metrics = Metrics::default();
+        metrics.capture_failed();
+        metrics.rejected();
+        metrics.rejected();
+        metrics.add_served_bytes(1000);
+        metrics.add_served_bytes(24);
+        let out = metrics.render();
+        assert_eq!(sample(&out, "comfy_capturer_capture_errors_total"), 1);
+        assert_eq!(sample(&out, "comfy_capturer_rejections_total"), 2);
+        assert_eq!(sample(&out, "comfy_capturer_served_bytes_total"), 1024);
+    }
+
+    #[test]
+    fn requests_are_labelled_by_route_and_status() {
+        let metrics = Metrics::default();
+        metrics.observe_request("capture_screen", 200);
+        metrics.observe_request("capture_screen", 200);
+        metrics.observe_request("capture_screen", 304);
+        metrics.observe_request("diff", 400);
+        let out = metrics.render();
+        let name = "comfy_capturer_http_requests_total";
+        let lines: Vec<&str> = out
+            .lines()
+            .filter(|line| line.starts_with(&format!("{}{{", name)))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "comfy_capturer_http_requests_total{route=\"capture_screen\",status=\"200\"} 2",
+                "comfy_capturer_http_requests_total{route=\"capture_screen\",status=\"304\"} 1",
+                "comfy_capturer_http_requests_total{route=\"diff\",status=\"400\"} 1",
+            ]
+        );
+    }
+}
@@ -0,0 +1,322 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+use crate::RecorderState;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use tauri::menu::{MenuItem, MenuItemKind, Submenu};
+use tauri::{AppHandle, Emitter, Manager, State, WebviewUrl, WebviewWindowBuilder, Window, Wry};
+
+pub const MAIN_WINDOW_LABEL: &str = "main";
+
+const REGION_LABEL_PREFIX: &str = "region-";
+
+/// 命名截图区域，名称 -> 窗口 label
+///
+/// 窗口创建后 label 不能修改，重命名时只修改名称，label 使用递增的编号。
+#[derive(Default)]
+pub struct Regions {
+    windows: Mutex<BTreeMap<String, String>>,
+    next_id: AtomicU32,
+}
+
+impl Regions {
+    pub fn names(&self) -> Vec<String> {
+        self.windows.lock().unwrap().keys().cloned().collect()
+    }
+
+    fn label(&self, name: &str) -> Option<String> {
+        self.windows.lock().unwrap().get(name).cloned()
+    }
+
+    fn name_of(&self, label: &str) -> Option<String> {
+        self.windows
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, window_label)| window_label.as_str() == label)
+            .map(|(name, _)| name.clone())
+    }
+
+    fn next_name(&self) -> String {
+        let windows = self.windows.lock().unwrap();
+        (1..)
+            .map(|i| format!("region{}", i))
+            .find(|name| !windows.contains_key(name))
+            .unwrap()
+    }
+}
+
+/// 区域名称会出现在 URL 中，只允许字母、数字、`-` 和 `_`
+fn validate_name(name: &str) -> Result<(), String> {
+    let is_valid = !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if is_valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "Invalid region name {:?}, only letters, digits, '-' and '_' are allowed",
+            name
+        ))
+    }
+}
+
+/// `None` 对应主窗口，否则查找命名区域窗口
+pub fn region_window(app_handle: &AppHandle, name: Option<&str>) -> Option<Window> {
+    let label = match name {
+        None => MAIN_WINDOW_LABEL.to_string(),
+        Some(name) => app_handle.state::<RecorderState>().regions.label(name)?,
+    };
+    let webview_window = app_handle.get_webview_window(&label)?;
+    Some(webview_window.as_ref().window())
+}
+
+pub fn create(app_handle: &AppHandle, name: Option<String>) -> Result<String, String> {
+    let state = app_handle.state::<RecorderState>();
+    let name = name.unwrap_or_else(|| state.regions.next_name());
+    validate_name(&name)?;
+
+    // 使用与主窗口相同的配置，保证透明、无边框、置顶等行为一致
+    let mut config = app_handle
+        .config()
+        .app
+        .windows
+        .first()
+        .cloned()
+        .ok_or("No window config")?;
+
+    // 先检查重名再分配编号，被拒绝的请求不占用 label
+    let label = {
+        let mut windows = state.regions.windows.lock().unwrap();
+        if windows.contains_key(&name) {
+            return Err(format!("Region {:?} already exists", name));
+        }
+        let label = format!(
+            "{}{}",
+            REGION_LABEL_PREFIX,
+            state.regions.next_id.fetch_add(1, Ordering::SeqCst) + 1
+        );
+        windows.insert(name.clone(), label.clone());
+        label
+    };
+
+    config.label = label.clone();
+    config.url = WebviewUrl::App("index.html".into());
+    config.title = region_title(&config.title, &name);
+
+    let result = WebviewWindowBuilder::from_config(app_handle, &config).and_then(|b| b.build());
+    if let Err(e) = result {
+        state.regions.windows.lock().unwrap().remove(&name);
+        return Err(e.to_string());
+    }
+
+    notify_regions_changed(app_handle);
+    Ok(name)
+}
+
+/// 区域窗口的标题，与前端 `set_window_title` 的格式一致
+fn region_title(base_title: &str, name: &str) -> String {
+    format!("{}[{}]", base_title, name)
+}
+
+pub fn rename(app_handle: &AppHandle, name: &str, new_name: String) -> Result<(), String> {
+    validate_name(&new_name)?;
+    let state = app_handle.state::<RecorderState>();
+    let label = {
+        let mut windows = state.regions.windows.lock().unwrap();
+        if windows.contains_key(&new_name) {
+            return Err(format!("Region {:?} already exists", new_name));
+        }
+        let label = windows
+            .remove(name)
+            .ok_or_else(|| format!("Region {:?} not found", name))?;
+        windows.insert(new_name.clone(), label.clone());
+        label
+    };
+
+    let base_title = app_handle
+        .config()
+        .app
+        .windows
+        .first()
+        .map(|config| config.title.clone())
+        .unwrap_or_default();
+    if let Some(window) = app_handle.get_webview_window(&label) {
+        if let Err(e) = window.set_title(&region_title(&base_title, &new_name)) {
+            tracing::error!("set region window title failed: {:?}", e);
+        }
+    }
+
+    notify_regions_changed(app_handle);
+    Ok(())
+}
+
+pub fn close(app_handle: &AppHandle, name: &str) -> Result<(), String> {
+    let state = app_handle.state::<RecorderState>();
+    let label = state
+        .regions
+        .label(name)
+        .ok_or_else(|| format!("Region {:?} not found", name))?;
+    if let Some(window) = app_handle.get_webview_window(&label) {
+        window.destroy().map_err(|e| e.to_string())?;
+    }
+    // 窗口销毁事件中也会调用 forget_window，这里先移除保证命令返回后列表已更新
+    forget_window(app_handle, &label);
+    Ok(())
+}
+
+/// 主窗口和所有命名区域窗口
+pub fn overlay_windows(app_handle: &AppHandle) -> Vec<Window> {
+    let labels: Vec<String> = {
+        let state = app_handle.state::<RecorderState>();
+        let windows = state.regions.windows.lock().unwrap();
+        windows.values().cloned().collect()
+    };
+    std::iter::once(MAIN_WINDOW_LABEL.to_string())
+        .chain(labels)
+        .filter_map(|label| app_handle.get_webview_window(&label))
+        .map(|webview_window| webview_window.as_ref().window())
+        .collect()
+}
+
+/// 区域窗口被关闭后从列表中移除
+pub fn forget_window(app_handle: &AppHandle, label: &str) {
+    if !label.starts_with(REGION_LABEL_PREFIX) {
+        return;
+    }
+    let state = app_handle.state::<RecorderState>();
+    state.snapped_sizes.lock().unwrap().remove(label);
+    let removed = {
+        let mut windows = state.regions.windows.lock().unwrap();
+        let before = windows.len();
+        windows.retain(|_, window_label| window_label != label);
+        windows.len() != before
+    };
+    if removed {
+        notify_regions_changed(app_handle);
+    }
+}
+
+fn notify_regions_changed(app_handle: &AppHandle) {
+    let state = app_handle.state::<RecorderState>();
+    let names = state.regions.names();
+
+    let tray_menu = state.tray_menu.lock().unwrap();
+    if let Some(tray_menu) = &*tray_menu {
+        if let Some(MenuItemKind::Submenu(submenu)) = tray_menu.get("regions") {
+            if let Err(e) = rebuild_tray_submenu(app_handle, &submenu, &names) {
+                tracing::error!("rebuild regions menu failed: {:?}", e);
+            }
+        }
+    }
+
+    app_handle.emit("regions_changed", names).unwrap();
+}
+
+/// 每个区域一个子菜单，包含重命名和关闭
+fn rebuild_tray_submenu(
+    app_handle: &AppHandle,
+    submenu: &Submenu<Wry>,
+    names: &[String],
+) -> tauri::Result<()> {
+    for item in submenu.items()? {
+        if let MenuItemKind::Submenu(region_menu) = item {
+            submenu.remove(&region_menu)?;
+        }
+    }
+
+    for name in names {
+        let rename_i = MenuItem::with_id(
+            app_handle,
+            format!("rename_region:{}", name),
+            "Rename",
+            true,
+            None::<&str>,
+        )?;
+        let close_i = MenuItem::with_id(
+            app_handle,
+            format!("close_region:{}", name),
+            "Close",
+            true,
+            None::<&str>,
+        )?;
+        let region_menu = Submenu::with_items(app_handle, name, true, &[&rename_i, &close_i])?;
+        submenu.append(&region_menu)?;
+    }
+
+    Ok(())
+}
+
+/// 处理托盘菜单中区域相关的项，返回是否已处理
+pub fn handle_menu_event(app_handle: &AppHandle, id: &str) -> bool {
+    if id == "new_region" {
+        // 在事件循环线程中同步创建窗口会在 Windows 上死锁，与 `create_region` 一样放到异步任务中
+        let app_handle = app_handle.clone();
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = create(&app_handle, None) {
+                tracing::error!("create region failed: {}", e);
+            }
+        });
+        return true;
+    }
+
+    if let Some(name) = id.strip_prefix("rename_region:") {
+        // 托盘菜单无法输入文字，交给区域窗口弹出设置面板
+        if let Some(label) = app_handle.state::<RecorderState>().regions.label(name) {
+            if let Some(window) = app_handle.get_webview_window(&label) {
+                let _ = window.set_focus();
+            }
+            app_handle
+                .emit_to(label.as_str(), "rename_region", name)
+                .unwrap();
+        }
+        return true;
+    }
+
+    if let Some(name) = id.strip_prefix("close_region:") {
+        if let Err(e) = close(app_handle, name) {
+            tracing::error!("close region failed: {}", e);
+        }
+        return true;
+    }
+
+    false
+}
+
+#[tauri::command]
+pub async fn create_region(app_handle: AppHandle, name: Option<String>) -> Result<String, String> {
+    create(&app_handle, name)
+}
+
+#[tauri::command]
+pub fn rename_region(app_handle: AppHandle, name: String, new_name: String) -> Result<(), String> {
+    rename(&app_handle, &name, new_name)
+}
+
+#[tauri::command]
+pub fn close_region(app_handle: AppHandle, name: String) -> Result<(), String> {
+    close(&app_handle, &name)
+}
+
+#[tauri::command]
+pub fn list_regions(state: State<'_, RecorderState>) -> Vec<String> {
+    state.regions.names()
+}
+
+/// 调用窗口对应的区域名称，主窗口返回 `None`
+#[tauri::command]
+pub fn get_region_name(state: State<'_, RecorderState>, window: Window) -> Option<String> {
+    state.regions.name_of(window.label())
+}
@@ -0,0 +1,592 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, RgbaImage};
+use serde::Deserialize;
+
+/// 输出图片的最大边长，防止一个查询参数就申请过大的内存
+const MAX_DIMENSION: u32 = 16384;
+
+/// 同时指定 `width` 和 `height` 时如何处理宽高比
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// 保持宽高比缩放到框内，输出可能小于指定尺寸
+    #[default]
+    Contain,
+    /// 保持宽高比铺满整个框，居中裁掉多余部分
+    Cover,
+    /// 拉伸到指定尺寸，不保持宽高比
+    Fill,
+    /// 同 `contain`，再用透明像素居中补齐到指定尺寸
+    Exact,
+}
+
+/// 缩放时使用的重采样算法
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizeFilter {
+    Nearest,
+    #[default]
+    Bilinear,
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    fn filter_type(self) -> FilterType {
+        match self {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Bilinear => FilterType::Triangle,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
+/// 截图的缩放参数，全部省略时不缩放
+///
+/// 只指定 `width` 或 `height` 时按原图宽高比计算另一边；`scale` 不能与二者同时使用。
+/// `snap` 在缩放之后应用。
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct ResizeOptions {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub scale: Option<f64>,
+    pub fit: Option<Fit>,
+    pub filter: Option<ResizeFilter>,
+    pub snap: Option<Snap>,
+    pub snap_mode: Option<SnapMode>,
+}
+
+impl ResizeOptions {
+    /// 在截图之前检查参数，避免截图之后才发现请求无效
+    pub fn validate(&self) -> Result<(), String> {
+        if self.scale.is_some() && (self.width.is_some() || self.height.is_some()) {
+            return Err("scale cannot be combined with width or height".to_string());
+        }
+        if let Some(scale) = self.scale {
+            if !scale.is_finite() || scale <= 0.0 {
+                return Err(format!("Invalid scale {}", scale));
+            }
+        }
+        for size in [self.width, self.height].into_iter().flatten() {
+            if size == 0 || size > MAX_DIMENSION {
+                return Err(format!(
+                    "width and height must be between 1 and {}",
+                    MAX_DIMENSION
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 按 `options` 缩放截图，再按 `snap` 调整尺寸；尺寸不变时直接返回原图
+pub fn resize_image(image: RgbaImage, options: &ResizeOptions) -> Result<RgbaImage, String> {
+    options.validate()?;
+
+    let (source_width, source_height) = image.dimensions();
+    if source_width == 0 || source_height == 0 {
+        return Ok(image);
+    }
+    let filter = options.filter.unwrap_or_default().filter_type();
+    let image = scale_image(image, options, filter)?;
+
+    Ok(match options.snap {
+        Some(snap) => snap_image(image, snap, options.snap_mode.unwrap_or_default(), filter),
+        None => image,
+    })
+}
+
+/// 处理 `width`、`height`、`scale` 和 `fit`
+fn scale_image(
+    image: RgbaImage,
+    options: &ResizeOptions,
+    filter: FilterType,
+) -> Result<RgbaImage, String> {
+    let (source_width, source_height) = image.dimensions();
+    let proportional = |size: u32, from: u32, to: u32| {
+        ((size as f64 * to as f64 / from as f64).round() as u32).max(1)
+    };
+
+    let (width, height, fit) = match (options.width, options.height, options.scale) {
+        (None, None, None) => return Ok(image),
+        (None, None, Some(scale)) => (
+            ((source_width as f64 * scale).round() as u32).max(1),
+            ((source_height as f64 * scale).round() as u32).max(1),
+            Fit::Fill,
+        ),
+        (Some(width), None, _) => (
+            width,
+            proportional(source_height, source_width, width),
+            Fit::Fill,
+        ),
+        (None, Some(height), _) => (
+            proportional(source_width, source_height, height),
+            height,
+            Fit::Fill,
+        ),
+        (Some(width), Some(height), _) => (width, height, options.fit.unwrap_or_default()),
+    };
+    if width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(format!(
+            "Resized image {}x{} exceeds {}x{}",
+            width, height, MAX_DIMENSION, MAX_DIMENSION
+        ));
+    }
+    if (width, height) == (source_width, source_height) {
+        return Ok(image);
+    }
+
+    let image = DynamicImage::ImageRgba8(image);
+    let resized = match fit {
+        Fit::Contain => image.resize(width, height, filter).into_rgba8(),
+        Fit::Cover => image.resize_to_fill(width, height, filter).into_rgba8(),
+        Fit::Fill => image.resize_exact(width, height, filter).into_rgba8(),
+        Fit::Exact => pad_to(
+            &image.resize(width, height, filter).into_rgba8(),
+            width,
+            height,
+        ),
+    };
+    Ok(resized)
+}
+
+/// 将 `image` 居中放到 `width`×`height` 的透明画布上，超出部分被裁掉
+fn pad_to(image: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    let mut canvas = RgbaImage::new(width, height);
+    let x = (width as i64 - image.width() as i64) / 2;
+    let y = (height as i64 - image.height() as i64) / 2;
+    imageops::replace(&mut canvas, image, x, y);
+    canvas
+}
+
+/// 面向 Stable Diffusion 的尺寸约束，避免 latent 尺寸与图片不一致
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Snap {
+    /// 宽高为 8 的倍数
+    #[serde(rename = "8")]
+    Multiple8,
+    /// 宽高为 64 的倍数
+    #[serde(rename = "64")]
+    Multiple64,
+    /// 宽高比最接近的 SDXL 训练分辨率
+    #[serde(rename = "sdxl")]
+    Sdxl,
+    /// 宽高比最接近的 Flux 分辨率（约 1MP，16 的倍数）
+    #[serde(rename = "flux")]
+    Flux,
+}
+
+/// 截图尺寸与 `Snap` 不一致时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapMode {
+    /// 倍数：居中裁掉多余的像素；分辨率档位：保持宽高比铺满后居中裁剪
+    #[default]
+    Crop,
+    /// 倍数：用透明像素补齐；分辨率档位：保持宽高比缩放后用透明像素补齐
+    Pad,
+    /// 直接拉伸到目标尺寸
+    Resize,
+}
+
+const SDXL_BUCKETS: &[(u32, u32)] = &[
+    (1024, 1024),
+    (1152, 896),
+    (896, 1152),
+    (1216, 832),
+    (832, 1216),
+    (1344, 768),
+    (768, 1344),
+    (1536, 640),
+    (640, 1536),
+];
+
+const FLUX_BUCKETS: &[(u32, u32)] = &[
+    (1024, 1024),
+    (1152, 912),
+    (912, 1152),
+    (1184, 880),
+    (880, 1184),
+    (1248, 832),
+    (832, 1248),
+    (1360, 768),
+    (768, 1360),
+    (1568, 672),
+    (672, 1568),
+];
+
+impl Snap {
+    fn multiple(self) -> Option<u32> {
+        match self {
+            Snap::Multiple8 => Some(8),
+            Snap::Multiple64 => Some(64),
+            Snap::Sdxl | Snap::Flux => None,
+        }
+    }
+
+    fn buckets(self) -> &'static [(u32, u32)] {
+        match self {
+            Snap::Sdxl => SDXL_BUCKETS,
+            Snap::Flux => FLUX_BUCKETS,
+            Snap::Multiple8 | Snap::Multiple64 => &[],
+        }
+    }
+
+    /// 最接近 `width`×`height` 的合法尺寸：倍数取四舍五入，分辨率档位取宽高比最接近的一档
+    pub fn nearest_size(self, width: u32, height: u32) -> (u32, u32) {
+        match self.multiple() {
+            Some(multiple) => (
+                round_to_multiple(width, multiple),
+                round_to_multiple(height, multiple),
+            ),
+            None => nearest_bucket(self.buckets(), width, height),
+        }
+    }
+}
+
+/// `value` 可能是超大窗口尺寸换算后饱和的 `u32::MAX`，加法不能溢出
+fn round_to_multiple(value: u32, multiple: u32) -> u32 {
+    (value.saturating_add(multiple / 2) / multiple * multiple).max(multiple)
+}
+
+fn nearest_bucket(buckets: &[(u32, u32)], width: u32, height: u32) -> (u32, u32) {
+    let aspect = (width.max(1) as f64 / height.max(1) as f64).ln();
+    buckets
+        .iter()
+        .copied()
+        .min_by(|a, b| {
+            let distance = |(w, h): (u32, u32)| ((w as f64 / h as f64).ln() - aspect).abs();
+            distance(*a).total_cmp(&distance(*b))
+        })
+        .unwrap_or((width, height))
+}
+
+/// 按 `snap` 和 `mode` 调整截图尺寸
+pub fn snap_image(image: RgbaImage, snap: Snap, mode: SnapMode, filter: FilterType) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let target = match (snap.multiple(), mode) {
+        (Some(multiple), SnapMode::Crop) => (
+            (width / multiple * multiple).max(multiple),
+            (height / multiple * multiple).max(multiple),
+        ),
+        (Some(multiple), SnapMode::Pad) => (
+            width.div_ceil(multiple) * multiple,
+            height.div_ceil(multiple) * multiple,
+        ),
+        _ => snap.nearest_size(width, height),
+    };
+    if target == (width, height) {
+        return image;
+    }
+
+    let (target_width, target_height) = target;
+    match (snap.multiple().is_some(), mode) {
+        // 倍数只差几个像素，裁剪和补齐都不缩放原图
+        (true, SnapMode::Crop) | (true, SnapMode::Pad) => {
+            pad_to(&image, target_width, target_height)
+        }
+        (false, SnapMode::Crop) => DynamicImage::ImageRgba8(image)
+            .resize_to_fill(target_width, target_height, filter)
+            .into_rgba8(),
+        (false, SnapMode::Pad) => {
+            let contained = DynamicImage::ImageRgba8(image)
+                .resize(target_width, target_height, filter)
+                .into_rgba8();
+            pad_to(&contained, target_width, target_height)
+        }
+        (_, SnapMode::Resize) => DynamicImage::ImageRgba8(image)
+            .resize_exact(target_width, target_height, filter)
+            .into_rgba8(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const GREEN: Rgba<u8> = Rgba([0, 255, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+
+    fn resize(width: u32, height: u32, options: ResizeOptions) -> Result<RgbaImage, String> {
+        resize_image(RgbaImage::from_pixel(width, height, RED), &options)
+    }
+
+    fn size(width: Option<u32>, height: Option<u32>, fit: Option<Fit>) -> ResizeOptions {
+        ResizeOptions {
+            width,
+            height,
+            fit,
+            ..Default::default()
+        }
+    }
+
+    fn scale(scale: f64) -> ResizeOptions {
+        ResizeOptions {
+            scale: Some(scale),
+            ..Default::default()
+        }
+    }
+
+    /// 左中右三条竖直色带
+    fn bands(band_width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(band_width * 3, height, |x, _| {
+            [RED, GREEN, BLUE][(x / band_width) as usize]
+        })
+    }
+
+    #[test]
+    fn no_options_keeps_the_image() {
+        let image = resize(400, 200, ResizeOptions::default()).unwrap();
+        assert_eq!(image.dimensions(), (400, 200));
+    }
+
+    #[test]
+    fn single_side_keeps_aspect_ratio() {
+        let image = resize(400, 200, size(Some(100), None, None)).unwrap();
+        assert_eq!(image.dimensions(), (100, 50));
+        let image = resize(400, 200, size(None, Some(100), None)).unwrap();
+        assert_eq!(image.dimensions(), (200, 100));
+        let image = resize(400, 200, scale(0.25)).unwrap();
+        assert_eq!(image.dimensions(), (100, 50));
+    }
+
+    #[test]
+    fn contain_fits_inside_the_box() {
+        let image = resize(400, 200, size(Some(100), Some(100), None)).unwrap();
+        assert_eq!(image.dimensions(), (100, 50));
+        let image = resize(200, 400, size(Some(100), Some(100), Some(Fit::Contain))).unwrap();
+        assert_eq!(image.dimensions(), (50, 100));
+    }
+
+    #[test]
+    fn cover_crops_the_center() {
+        let options = ResizeOptions {
+            filter: Some(ResizeFilter::Nearest),
+            ..size(Some(100), Some(100), Some(Fit::Cover))
+        };
+        let image = resize_image(bands(100, 100), &options).unwrap();
+        assert_eq!(image.dimensions(), (100, 100));
+        assert!(image.pixels().all(|pixel| *pixel == GREEN));
+    }
+
+    #[test]
+    fn fill_ignores_aspect_ratio() {
+        let image = resize(400, 200, size(Some(100), Some(100), Some(Fit::Fill))).unwrap();
+        assert_eq!(image.dimensions(), (100, 100));
+        assert_eq!(*image.get_pixel(0, 0), RED);
+    }
+
+    #[test]
+    fn exact_pads_with_transparent_pixels() {
+        let image = resize(400, 200, size(Some(100), Some(100), Some(Fit::Exact))).unwrap();
+        assert_eq!(image.dimensions(), (100, 100));
+        assert_eq!(image.get_pixel(50, 10).0[3], 0);
+        assert_eq!(*image.get_pixel(50, 50), RED);
+        assert_eq!(image.get_pixel(50, 90).0[3], 0);
+    }
+
+    #[test]
+    fn never_shrinks_below_one_pixel() {
+        let image = resize(1000, 10, size(Some(1), None, None)).unwrap();
+        assert_eq!(image.dimensions(), (1, 1));
+        let image = resize(1000, 10, scale(0.0001)).unwrap();
+        assert_eq!(image.dimensions(), (1, 1));
+        let image = resize(1, 1, size(Some(3), None, None)).unwrap();
+        assert_eq!(image.dimensions(), (3, 3));
+    }
+
+    #[test]
+    fn accepts_max_dimension() {
+        assert!(size(Some(MAX_DIMENSION), Some(MAX_DIMENSION), None)
+            .validate()
+            .is_ok());
+        let image = resize(100, 1, size(Some(MAX_DIMENSION), None, None)).unwrap();
+        assert_eq!(image.dimensions(), (MAX_DIMENSION, 164));
+    }
+
+    #[test]
+    fn rejects_sizes_above_max_dimension() {
+        assert!(size(Some(MAX_DIMENSION + 1), None, None)
+            .validate()
+            .is_err());
+        assert!(size(None, Some(MAX_DIMENSION + 1), None)
+            .validate()
+            .is_err());
+        // 参数本身合法，但按宽高比算出的另一边或缩放后的尺寸过大
+        assert!(resize(10, 1000, size(Some(200), None, None)).is_err());
+        assert!(resize(1000, 10, scale(17.0)).is_err());
+    }
+
+    #[test]
+    fn rejects_zero_and_invalid_values() {
+        assert!(size(Some(0), None, None).validate().is_err());
+        assert!(size(None, Some(0), None).validate().is_err());
+        assert!(size(Some(100), Some(0), None).validate().is_err());
+        for value in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(scale(value).validate().is_err(), "scale {}", value);
+        }
+        let options = ResizeOptions {
+            scale: Some(1.0),
+            ..size(Some(100), None, None)
+        };
+        assert!(options.validate().is_err());
+    }
+
+    #[test]
+    fn empty_image_is_returned_unchanged() {
+        let image = resize(0, 0, size(Some(100), Some(100), None)).unwrap();
+        assert_eq!(image.dimensions(), (0, 0));
+    }
+
+    fn snap(image: RgbaImage, snap: Snap, mode: SnapMode) -> RgbaImage {
+        snap_image(image, snap, mode, FilterType::Triangle)
+    }
+
+    #[test]
+    fn multiples_round_to_the_nearest_multiple() {
+        assert_eq!(Snap::Multiple8.nearest_size(1001, 555), (1000, 552));
+        assert_eq!(Snap::Multiple8.nearest_size(1020, 1021), (1024, 1024));
+        assert_eq!(Snap::Multiple8.nearest_size(3, 3), (8, 8));
+        assert_eq!(Snap::Multiple64.nearest_size(1000, 600), (1024, 576));
+        assert_eq!(Snap::Multiple64.nearest_size(1, 95), (64, 64));
+        assert_eq!(Snap::Multiple64.nearest_size(1, 96), (64, 128));
+        // 超大的尺寸向下取整，不会溢出
+        assert_eq!(
+            Snap::Multiple8.nearest_size(u32::MAX, u32::MAX - 3),
+            (u32::MAX - 7, u32::MAX - 7)
+        );
+        assert_eq!(
+            Snap::Multiple64.nearest_size(u32::MAX, 1),
+            (u32::MAX - 63, 64)
+        );
+    }
+
+    #[test]
+    fn sdxl_picks_the_nearest_aspect_ratio() {
+        assert_eq!(Snap::Sdxl.nearest_size(1000, 1000), (1024, 1024));
+        assert_eq!(Snap::Sdxl.nearest_size(1920, 1080), (1344, 768));
+        assert_eq!(Snap::Sdxl.nearest_size(1080, 1920), (768, 1344));
+        assert_eq!(Snap::Sdxl.nearest_size(4000, 1000), (1536, 640));
+        assert_eq!(Snap::Sdxl.nearest_size(1, 1), (1024, 1024));
+        assert!(SDXL_BUCKETS.iter().all(|(w, h)| w % 64 == 0 && h % 64 == 0));
+    }
+
+    #[test]
+    fn flux_picks_the_nearest_aspect_ratio() {
+        assert_eq!(Snap::Flux.nearest_size(1000, 1000), (1024, 1024));
+        assert_eq!(Snap::Flux.nearest_size(1920, 1080), (1360, 768));
+        assert_eq!(Snap::Flux.nearest_size(1000, 1330), (880, 1184));
+        assert_eq!(Snap::Flux.nearest_size(1000, 4000), (672, 1568));
+        assert!(FLUX_BUCKETS.iter().all(|(w, h)| w % 16 == 0 && h % 16 == 0));
+    }
+
+    #[test]
+    fn multiple_crop_rounds_down_without_scaling() {
+        let image = snap(
+            RgbaImage::from_pixel(1001, 555, RED),
+            Snap::Multiple8,
+            SnapMode::Crop,
+        );
+        assert_eq!(image.dimensions(), (1000, 552));
+        assert!(image.pixels().all(|pixel| *pixel == RED));
+        // 不足一个倍数时补齐到一个倍数
+        let image = snap(
+            RgbaImage::from_pixel(5, 70, RED),
+            Snap::Multiple64,
+            SnapMode::Crop,
+        );
+        assert_eq!(image.dimensions(), (64, 64));
+    }
+
+    #[test]
+    fn multiple_pad_rounds_up_with_transparent_pixels() {
+        let image = snap(
+            RgbaImage::from_pixel(1001, 555, RED),
+            Snap::Multiple8,
+            SnapMode::Pad,
+        );
+        assert_eq!(image.dimensions(), (1008, 560));
+        assert_eq!(image.get_pixel(0, 0).0[3], 0);
+        assert_eq!(*image.get_pixel(3, 2), RED);
+        assert_eq!(*image.get_pixel(1003, 556), RED);
+        assert_eq!(image.get_pixel(1004, 557).0[3], 0);
+    }
+
+    #[test]
+    fn multiple_resize_stretches_to_the_nearest_multiple() {
+        let image = snap(
+            RgbaImage::from_pixel(1000, 600, RED),
+            Snap::Multiple64,
+            SnapMode::Resize,
+        );
+        assert_eq!(image.dimensions(), (1024, 576));
+        assert!(image.pixels().all(|pixel| *pixel == RED));
+    }
+
+    #[test]
+    fn bucket_modes_produce_the_bucket_size() {
+        for mode in [SnapMode::Crop, SnapMode::Pad, SnapMode::Resize] {
+            let image = snap(RgbaImage::from_pixel(1001, 555, RED), Snap::Sdxl, mode);
+            assert_eq!(image.dimensions(), (1344, 768), "{:?}", mode);
+            let image = snap(RgbaImage::from_pixel(1001, 555, RED), Snap::Flux, mode);
+            assert_eq!(image.dimensions(), (1360, 768), "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn bucket_crop_fills_and_pad_letterboxes() {
+        let image = snap(
+            RgbaImage::from_pixel(1001, 555, RED),
+            Snap::Sdxl,
+            SnapMode::Crop,
+        );
+        assert_eq!(*image.get_pixel(672, 0), RED);
+        let image = snap(
+            RgbaImage::from_pixel(1001, 555, RED),
+            Snap::Sdxl,
+            SnapMode::Pad,
+        );
+        assert_eq!(image.get_pixel(672, 0).0[3], 0);
+        assert_eq!(*image.get_pixel(672, 384), RED);
+    }
+
+    #[test]
+    fn snapped_sizes_are_left_unchanged() {
+        for (snap_to, width, height) in [
+            (Snap::Multiple8, 1000, 552),
+            (Snap::Multiple64, 1024, 576),
+            (Snap::Sdxl, 1216, 832),
+            (Snap::Flux, 1248, 832),
+        ] {
+            let image = snap(
+                RgbaImage::from_pixel(width, height, RED),
+                snap_to,
+                SnapMode::Crop,
+            );
+            assert_eq!(image.dimensions(), (width, height));
+        }
+    }
+
+    #[test]
+    fn snap_applies_after_resizing() {
+        let options = ResizeOptions {
+            snap: Some(Snap::Multiple64),
+            snap_mode: Some(SnapMode::Pad),
+            ..size(Some(1000), None, None)
+        };
+        let image = resize_image(RgbaImage::from_pixel(2000, 1110, RED), &options).unwrap();
+        assert_eq!(image.dimensions(), (1024, 576));
+    }
+}
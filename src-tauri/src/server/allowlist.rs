@@ -0,0 +1,354 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+//! 监听地址与按 CIDR 限制来源地址
+//!
+//! `--bind`（`COMFY_CAPTURER_BIND`）指定监听地址，默认 `127.0.0.1`，可以是 `0.0.0.0` 或 IPv6 地址。
+//! `--allow`（`COMFY_CAPTURER_ALLOW`）为逗号分隔的 CIDR 列表，指定后其他来源的请求返回 403。
+//! 监听非回环地址又没有指定 `--allow` 时强制开启令牌认证。
+
+use super::text_response_with_status;
+use crate::config;
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use warp::http::{Response, StatusCode};
+use warp::hyper::Body;
+use warp::path::FullPath;
+use warp::{Filter, Rejection};
+
+/// 监听地址，无效时使用 `127.0.0.1`
+pub(super) fn bind_address() -> IpAddr {
+    let default = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    match config::option("bind", "COMFY_CAPTURER_BIND") {
+        // 允许写成 `[::]`
+        Some(value) => match value.trim_matches(|c| c == '[' || c == ']').parse() {
+            Ok(ip) => ip,
+            Err(_) => {
+                tracing::warn!("Invalid bind address {:?}, using {}", value, default);
+                default
+            }
+        },
+        None => default,
+    }
+}
+
+/// 监听非回环地址且没有指定允许列表时，局域网中的任何机器都能截图，必须开启令牌认证
+pub fn token_enforced() -> bool {
+    !bind_address().to_canonical().is_loopback()
+        && config::option("allow", "COMFY_CAPTURER_ALLOW").is_none()
+}
+
+/// 本机访问服务时 URL 中的主机名，监听所有地址时使用回环地址
+pub fn url_host() -> String {
+    match bind_address() {
+        IpAddr::V4(ip) if ip.is_unspecified() => Ipv4Addr::LOCALHOST.to_string(),
+        IpAddr::V6(ip) if ip.is_unspecified() => format!("[{}]", Ipv6Addr::LOCALHOST),
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{}]", ip),
+    }
+}
+
+/// 一个 CIDR 网段，不带前缀长度时只匹配这一个地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    fn parse(value: &str) -> Result<Self, String> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+        let network: IpAddr = address
+            .parse()
+            .map_err(|_| format!("Invalid address in {:?}", value))?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("Invalid prefix length in {:?}", value))?,
+            None => max_prefix,
+        };
+        // 来源地址比较前会转换为 IPv4，`::ffff:a.b.c.d/n` 同样换算成 IPv4 网段
+        match network {
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ipv4) if prefix >= 96 => Ok(Cidr {
+                    network: IpAddr::V4(ipv4),
+                    prefix: prefix - 96,
+                }),
+                Some(_) => Err(format!(
+                    "Prefix length of an IPv4-mapped address must be at least 96 in {:?}",
+                    value
+                )),
+                None => Ok(Cidr { network, prefix }),
+            },
+            IpAddr::V4(_) => Ok(Cidr { network, prefix }),
+        }
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        // 监听 `::` 时 IPv4 来源表示为 `::ffff:a.b.c.d`
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                prefix_matches(&network.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                prefix_matches(&network.octets(), &ip.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// `a` 与 `b` 的前 `prefix` 位是否相同
+fn prefix_matches(a: &[u8], b: &[u8], prefix: u8) -> bool {
+    let prefix = prefix as usize;
+    let (bytes, bits) = (prefix / 8, prefix % 8);
+    if a[..bytes] != b[..bytes] {
+        return false;
+    }
+    bits == 0 || (a[bytes] ^ b[bytes]) >> (8 - bits) == 0
+}
+
+/// 允许访问的来源地址，`None` 表示不限制
+#[derive(Debug)]
+pub(super) struct Allowlist(Option<Vec<Cidr>>);
+
+impl Allowlist {
+    /// 没有指定 `--allow` 时不限制
+    pub(super) fn from_config() -> Self {
+        match config::option("allow", "COMFY_CAPTURER_ALLOW") {
+            Some(value) => Allowlist::parse(&value),
+            None => Allowlist(None),
+        }
+    }
+
+    /// 逗号分隔的 CIDR 列表；无效的网段会被忽略，全部无效时拒绝所有来源，而不是放开限制
+    fn parse(value: &str) -> Self {
+        let ranges = value
+            .split(',')
+            .map(str::trim)
+            .filter(|range| !range.is_empty())
+            .filter_map(|range| match Cidr::parse(range) {
+                Ok(cidr) => Some(cidr),
+                Err(message) => {
+                    tracing::warn!("Ignoring allowlist entry: {}", message);
+                    None
+                }
+            })
+            .collect();
+        Allowlist(Some(ranges))
+    }
+
+    /// 不限制时总是允许；限制时未知的来源地址不允许
+    fn allows(&self, ip: Option<IpAddr>) -> bool {
+        match (&self.0, ip) {
+            (None, _) => true,
+            (Some(ranges), Some(ip)) => ranges.iter().any(|cidr| cidr.contains(ip)),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Forbidden;
+
+impl warp::reject::Reject for Forbidden {}
+
+#[derive(Serialize)]
+struct ForbiddenJson {
+    error: &'static str,
+}
+
+/// 来源地址不在允许列表中时拒绝请求并记录，由 `recover_forbidden` 返回 403
+pub(super) fn allow_peers(
+    allowlist: Arc<Allowlist>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and(warp::path::full())
+        .and_then(move |remote: Option<SocketAddr>, path: FullPath| {
+            let allowlist = allowlist.clone();
+            async move {
+                if allowlist.allows(remote.map(|remote| remote.ip())) {
+                    return Ok(());
+                }
+                let peer = remote.map_or("unknown".to_string(), |remote| remote.to_string());
+                tracing::warn!("Rejected request from {} to {}", peer, path.as_str());
+                Err(warp::reject::custom(Forbidden))
+            }
+        })
+        .untuple_one()
+}
+
+pub(super) async fn recover_forbidden(rejection: Rejection) -> Result<Response<Body>, Rejection> {
+    if rejection.find::<Forbidden>().is_none() {
+        return Err(rejection);
+    }
+    let body = ForbiddenJson {
+        error: "Address not allowed",
+    };
+    Ok(text_response_with_status(
+        StatusCode::FORBIDDEN,
+        "application/json",
+        serde_json::to_string(&body).unwrap(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn cidr(value: &str) -> Cidr {
+        Cidr::parse(value).unwrap()
+    }
+
+    #[test]
+    fn bare_address_is_a_single_host() {
+        let v4 = cidr("192.168.1.10");
+        assert_eq!(v4.prefix, 32);
+        assert!(v4.contains(ip("192.168.1.10")));
+        assert!(!v4.contains(ip("192.168.1.11")));
+
+        let v6 = cidr("fd00::1");
+        assert_eq!(v6.prefix, 128);
+        assert!(v6.contains(ip("fd00::1")));
+        assert!(!v6.contains(ip("fd00::2")));
+    }
+
+    #[test]
+    fn zero_prefix_matches_the_whole_family() {
+        let any_v4 = cidr("0.0.0.0/0");
+        assert!(any_v4.contains(ip("1.2.3.4")));
+        assert!(any_v4.contains(ip("255.255.255.255")));
+        assert!(!any_v4.contains(ip("fd00::1")));
+
+        let any_v6 = cidr("::/0");
+        assert!(any_v6.contains(ip("fd00::1")));
+        assert!(!any_v6.contains(ip("1.2.3.4")));
+    }
+
+    #[test]
+    fn byte_aligned_prefix() {
+        let range = cidr("10.0.0.0/8");
+        assert!(range.contains(ip("10.0.0.1")));
+        assert!(range.contains(ip("10.255.255.255")));
+        assert!(!range.contains(ip("11.0.0.0")));
+        assert!(!range.contains(ip("9.255.255.255")));
+    }
+
+    #[test]
+    fn prefix_within_a_byte() {
+        let range = cidr("192.168.1.4/31");
+        assert!(range.contains(ip("192.168.1.4")));
+        assert!(range.contains(ip("192.168.1.5")));
+        assert!(!range.contains(ip("192.168.1.3")));
+        assert!(!range.contains(ip("192.168.1.6")));
+
+        let range = cidr("172.16.0.0/12");
+        assert!(range.contains(ip("172.31.255.255")));
+        assert!(!range.contains(ip("172.32.0.0")));
+    }
+
+    #[test]
+    fn full_length_prefix_is_a_single_host() {
+        assert_eq!(cidr("192.168.1.10/32"), cidr("192.168.1.10"));
+        assert!(!cidr("192.168.1.10/32").contains(ip("192.168.1.11")));
+
+        let range = cidr("fd00::1/128");
+        assert!(range.contains(ip("fd00::1")));
+        assert!(!range.contains(ip("fd00::1:1")));
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("fd00::/129").is_err());
+        assert!(Cidr::parse("10.0.0.0/-1").is_err());
+        assert!(Cidr::parse("10.0.0.0/").is_err());
+        assert!(Cidr::parse("10.0.0.256").is_err());
+        assert!(Cidr::parse("localhost").is_err());
+    }
+
+    #[test]
+    fn ipv4_mapped_peer_matches_ipv4_range() {
+        // 监听 `::` 时 IPv4 客户端的地址
+        let range = cidr("192.168.1.0/24");
+        assert!(range.contains(ip("::ffff:192.168.1.20")));
+        assert!(!range.contains(ip("::ffff:192.168.2.20")));
+        assert!(cidr("127.0.0.1").contains(ip("::ffff:127.0.0.1")));
+    }
+
+    #[test]
+    fn ipv4_mapped_network_is_converted_to_ipv4() {
+        let range = cidr("::ffff:10.0.0.0/104");
+        assert_eq!(range, cidr("10.0.0.0/8"));
+        assert!(range.contains(ip("10.1.2.3")));
+        assert!(range.contains(ip("::ffff:10.1.2.3")));
+        assert!(!range.contains(ip("11.1.2.3")));
+
+        assert_eq!(cidr("::ffff:127.0.0.1"), cidr("127.0.0.1"));
+        // 前缀短于 96 位时已经超出了 IPv4 映射地址的范围
+        assert!(Cidr::parse("::ffff:0.0.0.0/95").is_err());
+    }
+
+    #[test]
+    fn mismatched_families_do_not_match() {
+        assert!(!cidr("10.0.0.0/8").contains(ip("fd00::1")));
+        assert!(!cidr("fd00::/8").contains(ip("10.0.0.1")));
+        // `::` 开头的 IPv6 网段不包含 IPv4 映射地址以外的 IPv4 来源
+        assert!(!cidr("::/96").contains(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn unrestricted_allowlist_allows_everyone() {
+        let allowlist = Allowlist(None);
+        assert!(allowlist.allows(Some(ip("8.8.8.8"))));
+        assert!(allowlist.allows(None));
+    }
+
+    #[test]
+    fn unknown_peer_is_denied() {
+        let allowlist = Allowlist::parse("0.0.0.0/0, ::/0");
+        assert!(allowlist.allows(Some(ip("8.8.8.8"))));
+        assert!(!allowlist.allows(None));
+    }
+
+    #[test]
+    fn allowlist_skips_invalid_entries() {
+        let allowlist = Allowlist::parse(" 127.0.0.1 , bogus, , 192.168.1.0/24 ");
+        assert_eq!(
+            allowlist.0,
+            Some(vec![cidr("127.0.0.1"), cidr("192.168.1.0/24")])
+        );
+        assert!(allowlist.allows(Some(ip("192.168.1.7"))));
+        assert!(!allowlist.allows(Some(ip("10.0.0.1"))));
+    }
+
+    #[test]
+    fn allowlist_with_only_invalid_entries_denies_everything() {
+        for value in ["bogus, 10.0.0.0/40", "", " , "] {
+            let allowlist = Allowlist::parse(value);
+            assert_eq!(allowlist.0, Some(Vec::new()));
+            assert!(!allowlist.allows(Some(ip("127.0.0.1"))));
+            assert!(!allowlist.allows(Some(ip("::1"))));
+            assert!(!allowlist.allows(None));
+        }
+    }
+}
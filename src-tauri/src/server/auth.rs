@@ -0,0 +1,244 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+//! HTTP API 的令牌认证
+//!
+//! 开启后每个请求都需要 `Authorization: Bearer <token>` 请求头或 `?token=<token>` 参数，
+//! 否则返回 401。令牌由应用生成并保存在配置目录中，也可以通过 `--api-token` 指定。
+
+use super::text_response_with_status;
+use crate::{config, RecorderState};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use tauri::{AppHandle, Manager};
+use warp::http::{Response, StatusCode};
+use warp::hyper::Body;
+use warp::{Filter, Rejection};
+
+const TOKEN_FILE: &str = "api_token";
+/// 生成的令牌的字节数，十六进制后为 64 个字符
+const TOKEN_BYTES: usize = 32;
+
+/// 命令行或环境变量指定的令牌，指定时默认开启认证
+pub fn configured_token() -> Option<String> {
+    config::option("api-token", "COMFY_CAPTURER_API_TOKEN")
+}
+
+/// 当前的令牌，第一次调用时读取保存的令牌，不存在时生成并保存
+pub fn api_token(app_handle: &AppHandle) -> String {
+    let state = app_handle.state::<RecorderState>();
+    let mut token = state.api_token.lock().unwrap();
+    token
+        .get_or_insert_with(|| configured_token().unwrap_or_else(|| load_or_create(app_handle)))
+        .clone()
+}
+
+fn load_or_create(app_handle: &AppHandle) -> String {
+    let path = match app_handle.path().app_config_dir() {
+        Ok(dir) => dir.join(TOKEN_FILE),
+        Err(e) => {
+            tracing::warn!("Failed to resolve the config directory: {}", e);
+            return generate_token();
+        }
+    };
+    if let Ok(token) = fs::read_to_string(&path) {
+        let token = token.trim();
+        if !token.is_empty() {
+            return token.to_string();
+        }
+    }
+
+    let token = generate_token();
+    let saved = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| write_token(&path, &token));
+    if let Err(e) = saved {
+        // 保存失败时仍然可以使用，只是重启后会换一个新的令牌
+        tracing::warn!("Failed to save the API token to {:?}: {}", path, e);
+    }
+    token
+}
+
+/// 令牌文件只允许当前用户读写
+#[cfg(unix)]
+fn write_token(path: &Path, token: &str) -> io::Result<()> {
+    use std::fs::{OpenOptions, Permissions};
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // `mode` 只在创建文件时生效，已经存在的文件也要收紧权限
+    file.set_permissions(Permissions::from_mode(0o600))?;
+    file.write_all(token.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_token(path: &Path, token: &str) -> io::Result<()> {
+    fs::File::create(path)?.write_all(token.as_bytes())
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    getrandom::getrandom(&mut bytes).expect("Failed to generate the API token");
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// 比较耗时只取决于长度，与令牌的内容无关
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// `Authorization` 请求头中的 Bearer 令牌，方案名不区分大小写
+fn bearer_token(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.trim().split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+#[derive(Serialize)]
+struct UnauthorizedJson {
+    error: &'static str,
+}
+
+/// 没有开启认证或令牌正确时通过，否则拒绝请求，由 `recover_unauthorized` 返回 401
+pub(super) fn authorize(
+    app_handle: AppHandle,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and(warp::query::<TokenQuery>())
+        .and_then(move |authorization: Option<String>, query: TokenQuery| {
+            let app_handle = app_handle.clone();
+            async move {
+                let state = app_handle.state::<RecorderState>();
+                if !state.require_token.load(Ordering::SeqCst) {
+                    return Ok(());
+                }
+                let bearer = authorization.as_deref().and_then(bearer_token);
+                let expected = api_token(&app_handle);
+                match bearer.or(query.token.as_deref()) {
+                    Some(token) if constant_time_eq(token.as_bytes(), expected.as_bytes()) => {
+                        Ok(())
+                    }
+                    _ => Err(warp::reject::custom(Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+pub(super) async fn recover_unauthorized(
+    rejection: Rejection,
+) -> Result<Response<Body>, Rejection> {
+    if rejection.find::<Unauthorized>().is_none() {
+        return Err(rejection);
+    }
+    let body = UnauthorizedJson {
+        error: "Missing or invalid token",
+    };
+    let mut response = text_response_with_status(
+        StatusCode::UNAUTHORIZED,
+        "application/json",
+        serde_json::to_string(&body).unwrap(),
+    );
+    response
+        .headers_mut()
+        .insert("WWW-Authenticate", "Bearer".parse().unwrap());
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_time_eq_compares_contents() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"Secret"));
+    }
+
+    #[test]
+    fn constant_time_eq_rejects_different_lengths() {
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"secret", b"secre"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+
+    #[test]
+    fn bearer_scheme_is_case_insensitive() {
+        assert_eq!(bearer_token("Bearer abc"), Some("abc"));
+        assert_eq!(bearer_token("bearer abc"), Some("abc"));
+        assert_eq!(bearer_token("BEARER abc"), Some("abc"));
+    }
+
+    #[test]
+    fn bearer_token_is_trimmed() {
+        assert_eq!(bearer_token("Bearer   abc  "), Some("abc"));
+        assert_eq!(bearer_token("  Bearer abc"), Some("abc"));
+    }
+
+    #[test]
+    fn other_schemes_and_empty_tokens_are_ignored() {
+        assert_eq!(bearer_token("Basic YWJjOmRlZg=="), None);
+        assert_eq!(bearer_token("Bearerabc"), None);
+        assert_eq!(bearer_token("Bearer"), None);
+        assert_eq!(bearer_token("Bearer   "), None);
+        assert_eq!(bearer_token("abc"), None);
+        assert_eq!(bearer_token(""), None);
+    }
+
+    #[test]
+    fn generated_tokens_are_random_hex() {
+        let token = generate_token();
+        assert_eq!(token.len(), TOKEN_BYTES * 2);
+        assert!(token.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(token, generate_token());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn token_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("api_token_test_{}", std::process::id()));
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_token(&path, "new").unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(contents, "new");
+    }
+}
@@ -0,0 +1,186 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+//! `/diff`：比较当前截图与上一次截图或命名的参考帧，返回变化的区域
+
+use super::{bad_request, error_response, text_response, CaptureQuery, Capturer, ResponseKind};
+use crate::diff::{self, DiffRect};
+use crate::geometry::ScreenRect;
+use image::{GrayImage, ImageFormat, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+use tauri::AppHandle;
+use warp::http::{Response, StatusCode};
+use warp::hyper::Body;
+
+/// 归并变化区域时使用的网格大小
+const REGION_CELL: u32 = 16;
+/// 最多保存的参考帧数量
+const MAX_REFERENCES: usize = 16;
+
+/// `/diff` 特有的查询参数
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct DiffQuery {
+    /// 与之比较的参考帧名称，省略时与同一区域的上一次截图比较
+    reference: Option<String>,
+    /// 比较之后将当前截图保存为参考帧
+    save: Option<String>,
+}
+
+/// 通过 `save` 保存的参考帧
+#[derive(Default)]
+pub(super) struct References(Mutex<HashMap<String, Arc<RgbaImage>>>);
+
+impl References {
+    fn get(&self, name: &str) -> Option<Arc<RgbaImage>> {
+        self.0.lock().unwrap().get(name).cloned()
+    }
+
+    fn insert(&self, name: String, image: Arc<RgbaImage>) -> Result<(), String> {
+        let mut references = self.0.lock().unwrap();
+        if !references.contains_key(&name) && references.len() >= MAX_REFERENCES {
+            return Err(format!(
+                "At most {} reference frames can be saved",
+                MAX_REFERENCES
+            ));
+        }
+        references.insert(name, image);
+        Ok(())
+    }
+}
+
+/// `/diff` 的 JSON 响应，`regions` 为截图坐标，`rect` 为截取的屏幕区域
+#[derive(Debug, Serialize)]
+struct DiffJson {
+    /// 比较的基准：`previous`、参考帧名称，没有基准时为 `null` 并视为整张图都发生了变化
+    reference: Option<String>,
+    changed_ratio: f64,
+    changed_pixels: u64,
+    total_pixels: u64,
+    /// RGBA 通道差值绝对值的平均，范围 0 到 255
+    mean_error: f64,
+    regions: Vec<DiffRect>,
+    width: u32,
+    height: u32,
+    rect: ScreenRect,
+    timestamp: u64,
+    sequence: u64,
+}
+
+pub(super) async fn handle_diff(
+    app_handle: AppHandle,
+    capturer: Arc<Capturer>,
+    references: Arc<References>,
+    region: Option<String>,
+    query: CaptureQuery,
+    diff_query: DiffQuery,
+) -> Result<Response<Body>, warp::Rejection> {
+    let as_mask = match query.response {
+        None | Some(ResponseKind::Json) => false,
+        Some(ResponseKind::Mask) => true,
+        Some(ResponseKind::Image) | Some(ResponseKind::Dataurl) => {
+            return Ok(bad_request(
+                "/diff only supports as=json or as=mask".to_string(),
+            ));
+        }
+    };
+
+    let (reference_name, reference) = match diff_query.reference {
+        Some(name) => match references.get(&name) {
+            Some(image) => (Some(name), Some(image)),
+            None => {
+                return Ok(error_response(
+                    StatusCode::NOT_FOUND,
+                    format!("Reference frame {:?} not found", name),
+                ));
+            }
+        },
+        None => match capturer.previous(region.as_deref(), &query) {
+            Some((_, image)) => (Some("previous".to_string()), Some(image)),
+            None => (None, None),
+        },
+    };
+
+    // 不复用缓存的截图，否则可能与上一次截图是同一帧
+    let captured = match capturer
+        .capture_fresh(&app_handle, region.as_deref(), &query)
+        .await
+    {
+        Ok(captured) => captured,
+        Err(failure) => return failure.into_response(),
+    };
+    if let Some(name) = diff_query.save {
+        if let Err(message) = references.insert(name, captured.image.clone()) {
+            return Ok(bad_request(message));
+        }
+    }
+
+    let image = captured.image.clone();
+    let compared = tokio::task::spawn_blocking(move || {
+        // 没有基准时与空图比较，尺寸不同即视为全部变化
+        let reference = reference.unwrap_or_default();
+        let stats = diff::compare(&reference, &image);
+        let mask = diff::change_mask(&reference, &image);
+        let regions = diff::changed_regions(&mask, REGION_CELL);
+        (stats, mask, regions)
+    })
+    .await;
+    let (stats, mask, regions) = match compared {
+        Ok(compared) => compared,
+        Err(e) => {
+            return Ok(error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                e.to_string(),
+            ))
+        }
+    };
+
+    if as_mask {
+        return Ok(match encode_mask(&mask) {
+            Ok(bytes) => Response::builder()
+                .header("Content-Type", "image/png")
+                .header("X-Changed-Ratio", stats.changed_ratio().to_string())
+                .header("X-Mean-Error", stats.mean_error.to_string())
+                .body(Body::from(bytes))
+                .unwrap(),
+            Err(e) => error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to encode mask: {}", e),
+            ),
+        });
+    }
+
+    let body = DiffJson {
+        reference: reference_name,
+        changed_ratio: stats.changed_ratio(),
+        changed_pixels: stats.changed_pixels,
+        total_pixels: stats.total_pixels,
+        mean_error: stats.mean_error,
+        regions,
+        width: captured.image.width(),
+        height: captured.image.height(),
+        rect: captured.rect,
+        timestamp: captured.timestamp,
+        sequence: captured.sequence,
+    };
+    Ok(text_response(
+        "application/json",
+        serde_json::to_string(&body).unwrap(),
+    ))
+}
+
+fn encode_mask(mask: &GrayImage) -> image::ImageResult<Vec<u8>> {
+    let mut cursor = Cursor::new(Vec::new());
+    mask.write_to(&mut cursor, ImageFormat::Png)?;
+    Ok(cursor.into_inner())
+}
@@ -0,0 +1,1239 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+mod allowlist;
+mod auth;
+mod compare;
+mod single_flight;
+mod status;
+mod stream;
+mod wait;
+mod ws;
+
+use crate::capture::app_window::{capture_window, WindowSelector};
+use crate::capture::monitor::{find_monitor_rect, monitor_rects};
+use crate::capture::{
+    capture_region, capture_region_hiding, monitor_geometries, overlay_monitor_rect,
+    overlay_screen_rect, CaptureOptions, ScreenRect,
+};
+use crate::diff::content_hash;
+use crate::encode::{encode_image, EncodeOptions, OutputFormat};
+use crate::geometry::monitor_at;
+use crate::metrics::Metrics;
+use crate::resize::{resize_image, ResizeOptions};
+use crate::settle::SettleOptions;
+use crate::{regions, RecorderState};
+use allowlist::{allow_peers, bind_address, recover_forbidden, Allowlist};
+pub use allowlist::{token_enforced, url_host};
+pub use auth::{api_token, configured_token};
+use auth::{authorize, recover_unauthorized};
+use compare::{handle_diff, DiffQuery, References};
+use image::RgbaImage;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+pub use single_flight::Capturer;
+use status::handle_status;
+pub use status::CaptureStatus;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use stream::{handle_stream, StreamQuery};
+use tauri::{AppHandle, Manager, Window};
+use tokio::sync::{oneshot, watch};
+use wait::Waited;
+use warp::http::{Response, StatusCode};
+use warp::hyper::Body;
+use warp::Filter;
+use ws::{handle_ws, WsQuery};
+use xcap::XCapError;
+
+/// 429 响应的 `Retry-After`，单位为秒
+const RETRY_AFTER_SECONDS: u32 = 1;
+
+/// `/capture_screen` 的查询参数
+///
+/// `x`、`y`、`w`、`h`、`monitor`、`origin` 全部省略时截取覆盖窗口，否则截取其指定的区域。
+#[derive(Debug, Clone, Default, Deserialize)]
+struct CaptureQuery {
+    x: Option<i32>,
+    y: Option<i32>,
+    w: Option<u32>,
+    h: Option<u32>,
+    /// 显示器序号，与 `list_monitors` 的顺序一致
+    monitor: Option<usize>,
+    origin: Option<RegionOrigin>,
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    include_cursor: bool,
+    #[serde(rename = "as")]
+    response: Option<ResponseKind>,
+    /// 长轮询：画面变化超过 `threshold` 时才返回，`timeout_ms` 内没有变化时返回 304
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    wait_for_change: bool,
+    /// 变化像素比例的阈值，0 到 1，默认为 0，即任何变化
+    threshold: Option<f64>,
+    timeout_ms: Option<u64>,
+    /// 返回第一帧连续 `settle_ms` 没有变化的截图，最多等待 `max_wait_ms`
+    settle_ms: Option<u64>,
+    max_wait_ms: Option<u64>,
+}
+
+/// `as` 参数：响应的形式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ResponseKind {
+    /// 图片本身
+    #[default]
+    Image,
+    /// base64 图片与截图信息，与 `.json` 路径相同
+    Json,
+    /// `data:` URL 文本
+    Dataurl,
+    /// 变化像素的 PNG 遮罩，只用于 `/diff`
+    Mask,
+}
+
+/// JSON 响应，`width`、`height` 为输出图片的尺寸，`rect` 为截取的屏幕区域
+#[derive(Debug, Serialize)]
+struct CaptureJson {
+    image: String,
+    content_type: &'static str,
+    width: u32,
+    height: u32,
+    scale_factor: f64,
+    rect: ScreenRect,
+    /// 截图完成的时间，毫秒级 Unix 时间戳
+    timestamp: u64,
+    /// 本次运行中的截图序号，从 1 开始
+    sequence: u64,
+}
+
+/// `x`、`y` 的坐标原点
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RegionOrigin {
+    /// 虚拟桌面坐标，未指定 `monitor` 时的默认值
+    Screen,
+    /// 显示器左上角，指定 `monitor` 时的默认值
+    Monitor,
+    /// 覆盖窗口客户区左上角
+    Overlay,
+}
+
+impl CaptureQuery {
+    fn has_region(&self) -> bool {
+        self.x.is_some()
+            || self.y.is_some()
+            || self.w.is_some()
+            || self.h.is_some()
+            || self.monitor.is_some()
+            || self.origin.is_some()
+    }
+
+    fn settle(&self) -> Option<SettleOptions> {
+        self.settle_ms.map(|settle_ms| SettleOptions {
+            settle_ms,
+            max_wait_ms: self.max_wait_ms,
+        })
+    }
+
+    fn options(&self) -> CaptureOptions {
+        CaptureOptions {
+            include_cursor: self.include_cursor,
+        }
+    }
+
+    /// 计算要截取的屏幕区域，并检查它是否完整地位于一个显示器内
+    fn resolve(&self, window: &Window) -> Result<ScreenRect, String> {
+        let x = self.x.unwrap_or(0);
+        let y = self.y.unwrap_or(0);
+        let origin = self.origin.unwrap_or(if self.monitor.is_some() {
+            RegionOrigin::Monitor
+        } else {
+            RegionOrigin::Screen
+        });
+
+        let indexed_monitor = match self.monitor {
+            Some(index) => {
+                let monitors = monitor_rects().map_err(|e| e.to_string())?;
+                let monitor = monitors
+                    .get(index)
+                    .copied()
+                    .ok_or_else(|| format!("Monitor {} not found", index))?;
+                Some(monitor)
+            }
+            None => None,
+        };
+
+        let (base_x, base_y, default_size, bounds) = match origin {
+            RegionOrigin::Screen => {
+                let bounds = match indexed_monitor {
+                    Some(monitor) => monitor,
+                    None => monitor_rects()
+                        .map_err(|e| e.to_string())?
+                        .into_iter()
+                        .find(|monitor| monitor.contains_point(x, y))
+                        .ok_or_else(|| format!("({}, {}) is not on any monitor", x, y))?,
+                };
+                (0, 0, None, bounds)
+            }
+            RegionOrigin::Monitor => {
+                let bounds = match indexed_monitor {
+                    Some(monitor) => monitor,
+                    None => overlay_monitor_rect(window).map_err(|e| e.to_string())?,
+                };
+                let remaining = (remaining(bounds.width, x), remaining(bounds.height, y));
+                (bounds.x, bounds.y, Some(remaining), bounds)
+            }
+            RegionOrigin::Overlay => {
+                let overlay = overlay_screen_rect(window).map_err(|e| e.to_string())?;
+                let bounds = match indexed_monitor {
+                    Some(monitor) => monitor,
+                    None => overlay_monitor_rect(window).map_err(|e| e.to_string())?,
+                };
+                let remaining = (remaining(overlay.width, x), remaining(overlay.height, y));
+                (overlay.x, overlay.y, Some(remaining), bounds)
+            }
+        };
+
+        let (width, height) = match (self.w, self.h, default_size) {
+            (Some(w), Some(h), _) => (w, h),
+            (w, h, Some((default_w, default_h))) => {
+                (w.unwrap_or(default_w), h.unwrap_or(default_h))
+            }
+            _ => return Err("w and h are required for screen coordinates".to_string()),
+        };
+        if width == 0 || height == 0 {
+            return Err("Capture region is empty".to_string());
+        }
+        // 先按尺寸拒绝，避免为超大的区域分配内存
+        if width > bounds.width || height > bounds.height {
+            return Err(format!(
+                "Capture region {}x{} is larger than the monitor {}x{}",
+                width, height, bounds.width, bounds.height
+            ));
+        }
+
+        let left = base_x as i64 + x as i64;
+        let top = base_y as i64 + y as i64;
+        let (Ok(left), Ok(top)) = (i32::try_from(left), i32::try_from(top)) else {
+            return Err(format!(
+                "Capture region origin ({}, {}) is out of range",
+                left, top
+            ));
+        };
+        let rect = ScreenRect {
+            x: left,
+            y: top,
+            width,
+            height,
+        };
+        if !bounds.contains_rect(&rect) {
+            return Err(format!(
+                "Capture region {:?} is outside the monitor bounds {:?}",
+                rect, bounds
+            ));
+        }
+
+        Ok(rect)
+    }
+}
+
+/// 从 `offset` 到 `size` 末端的长度，`offset` 超出时为 0
+fn remaining(size: u32, offset: i32) -> u32 {
+    (size as i64 - offset as i64).clamp(0, size as i64) as u32
+}
+
+pub async fn serve(app_handle: AppHandle, port: u16, shutdown: oneshot::Receiver<()>) {
+    let capturer = app_handle.state::<RecorderState>().capturer.clone();
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let stopping = Shutdown(shutdown_rx);
+
+    let capture_route = {
+        let app_handle = app_handle.clone();
+        warp::path!("capture_screen")
+            .and(query::<CaptureQuery>())
+            .and(query::<ResizeOptions>())
+            .and(encode_options())
+            .and(if_none_match())
+            .and(with_capturer(capturer.clone()))
+            .and_then(move |query, resize, encode, if_none_match, capturer| {
+                handle_capture(
+                    app_handle.clone(),
+                    capturer,
+                    None,
+                    query,
+                    resize,
+                    encode,
+                    if_none_match,
+                )
+            })
+    };
+
+    let json_capture_route = {
+        let app_handle = app_handle.clone();
+        warp::path!("capture_screen.json")
+            .and(query::<CaptureQuery>())
+            .and(query::<ResizeOptions>())
+            .and(encode_options())
+            .and(if_none_match())
+            .and(with_capturer(capturer.clone()))
+            .and_then(
+                move |query: CaptureQuery, resize, encode, if_none_match, capturer| {
+                    let query = CaptureQuery {
+                        response: Some(ResponseKind::Json),
+                        ..query
+                    };
+                    handle_capture(
+                        app_handle.clone(),
+                        capturer,
+                        None,
+                        query,
+                        resize,
+                        encode,
+                        if_none_match,
+                    )
+                },
+            )
+    };
+
+    // 区域名称不包含 `.`，`/capture_screen/{name}.json` 同样返回 JSON
+    let region_capture_route = {
+        let app_handle = app_handle.clone();
+        warp::path!("capture_screen" / String)
+            .and(query::<CaptureQuery>())
+            .and(query::<ResizeOptions>())
+            .and(encode_options())
+            .and(if_none_match())
+            .and(with_capturer(capturer.clone()))
+            .and_then(
+                move |name: String,
+                      query: CaptureQuery,
+                      resize,
+                      encode,
+                      if_none_match,
+                      capturer| {
+                    let (name, query) = match name.strip_suffix(".json") {
+                        Some(name) => (
+                            name.to_string(),
+                            CaptureQuery {
+                                response: Some(ResponseKind::Json),
+                                ..query
+                            },
+                        ),
+                        None => (name, query),
+                    };
+                    handle_capture(
+                        app_handle.clone(),
+                        capturer,
+                        Some(name),
+                        query,
+                        resize,
+                        encode,
+                        if_none_match,
+                    )
+                },
+            )
+    };
+
+    let window_capture_route = {
+        let app_handle = app_handle.clone();
+        warp::path!("capture_window")
+            .and(query::<WindowSelector>())
+            .and(query::<ResizeOptions>())
+            .and(encode_options())
+            .and(if_none_match())
+            .and(with_capturer(capturer.clone()))
+            .and_then(move |selector, resize, encode, if_none_match, capturer| {
+                handle_capture_window(
+                    app_handle.clone(),
+                    capturer,
+                    selector,
+                    resize,
+                    encode,
+                    if_none_match,
+                )
+            })
+    };
+
+    let monitor_capture_route = {
+        let app_handle = app_handle.clone();
+        warp::path!("capture_monitor" / String)
+            .and(query::<ResizeOptions>())
+            .and(encode_options())
+            .and(if_none_match())
+            .and(with_capturer(capturer.clone()))
+            .and_then(
+                move |key: String, resize, encode, if_none_match, capturer| {
+                    let app_handle = app_handle.clone();
+                    handle_capture_monitor(app_handle, capturer, key, resize, encode, if_none_match)
+                },
+            )
+    };
+
+    let stream_route = {
+        let app_handle = app_handle.clone();
+        warp::path!("stream")
+            .and(query::<CaptureQuery>())
+            .and(query::<ResizeOptions>())
+            .and(query::<StreamQuery>())
+            .and(with_capturer(capturer.clone()))
+            .and(with_shutdown(stopping.clone()))
+            .and_then(move |query, resize, stream, capturer, shutdown| {
+                let app_handle = app_handle.clone();
+                handle_stream(app_handle, capturer, shutdown, None, query, resize, stream)
+            })
+    };
+
+    let region_stream_route = {
+        let app_handle = app_handle.clone();
+        warp::path!("stream" / String)
+            .and(query::<CaptureQuery>())
+            .and(query::<ResizeOptions>())
+            .and(query::<StreamQuery>())
+            .and(with_capturer(capturer.clone()))
+            .and(with_shutdown(stopping.clone()))
+            .and_then(move |name, query, resize, stream, capturer, shutdown| {
+                let (app_handle, region) = (app_handle.clone(), Some(name));
+                handle_stream(
+                    app_handle, capturer, shutdown, region, query, resize, stream,
+                )
+            })
+    };
+
+    let ws_route = {
+        let app_handle = app_handle.clone();
+        warp::path!("ws")
+            .and(query::<CaptureQuery>())
+            .and(query::<ResizeOptions>())
+            .and(encode_options())
+            .and(query::<WsQuery>())
+            .and(warp::ws())
+            .and(with_capturer(capturer.clone()))
+            .and(with_shutdown(stopping.clone()))
+            .and_then(
+                move |query, resize, encode, ws_query, ws, capturer, shutdown| {
+                    handle_ws(
+                        app_handle.clone(),
+                        capturer,
+                        shutdown,
+                        None,
+                        query,
+                        resize,
+                        encode,
+                        ws_query,
+                        ws,
+                    )
+                },
+            )
+    };
+
+    let region_ws_route = {
+        let app_handle = app_handle.clone();
+        warp::path!("ws" / String)
+            .and(query::<CaptureQuery>())
+            .and(query::<ResizeOptions>())
+            .and(encode_options())
+            .and(query::<WsQuery>())
+            .and(warp::ws())
+            .and(with_capturer(capturer.clone()))
+            .and(with_shutdown(stopping.clone()))
+            .and_then(
+                move |name, query, resize, encode, ws_query, ws, capturer, shutdown| {
+                    handle_ws(
+                        app_handle.clone(),
+                        capturer,
+                        shutdown,
+                        Some(name),
+                        query,
+                        resize,
+                        encode,
+                        ws_query,
+                        ws,
+                    )
+                },
+            )
+    };
+
+    let references = Arc::new(References::default());
+
+    let diff_route = {
+        let app_handle = app_handle.clone();
+        let references = references.clone();
+        warp::path!("diff")
+            .and(query::<CaptureQuery>())
+            .and(query::<DiffQuery>())
+            .and(with_capturer(capturer.clone()))
+            .and_then(move |query, diff_query, capturer| {
+                let references = references.clone();
+                handle_diff(
+                    app_handle.clone(),
+                    capturer,
+                    references,
+                    None,
+                    query,
+                    diff_query,
+                )
+            })
+    };
+
+    let region_diff_route = {
+        let app_handle = app_handle.clone();
+        warp::path!("diff" / String)
+            .and(query::<CaptureQuery>())
+            .and(query::<DiffQuery>())
+            .and(with_capturer(capturer.clone()))
+            .and_then(move |name, query, diff_query, capturer| {
+                let references = references.clone();
+                let region = Some(name);
+                handle_diff(
+                    app_handle.clone(),
+                    capturer,
+                    references,
+                    region,
+                    query,
+                    diff_query,
+                )
+            })
+    };
+
+    let addr = SocketAddr::new(bind_address(), port);
+    let require_token = &app_handle.state::<RecorderState>().require_token;
+    if token_enforced() && !require_token.swap(true, Ordering::SeqCst) {
+        tracing::warn!(
+            "Listening on {} without an allowlist, the API token is required",
+            addr
+        );
+    }
+    let status_route = {
+        let app_handle = app_handle.clone();
+        warp::path!("status")
+            .and(warp::get())
+            .map(move || handle_status(&app_handle, addr))
+    };
+
+    let metrics_route = {
+        let app_handle = app_handle.clone();
+        warp::path!("metrics").and(warp::get()).map(move || {
+            let state = app_handle.state::<RecorderState>();
+            text_response(
+                "text/plain; version=0.0.4; charset=utf-8",
+                state.metrics.render(),
+            )
+        })
+    };
+
+    let default_route = warp::any().map(|| {
+        warp::reply::with_status("Not Found".to_string(), warp::http::StatusCode::NOT_FOUND)
+    });
+
+    let routes = allow_peers(Arc::new(Allowlist::from_config()))
+        .and(authorize(app_handle.clone()))
+        .and(
+            capture_route
+                .or(json_capture_route)
+                .or(region_capture_route)
+                .or(window_capture_route)
+                .or(monitor_capture_route)
+                .or(stream_route)
+                .or(region_stream_route)
+                .or(ws_route)
+                .or(region_ws_route)
+                .or(diff_route)
+                .or(region_diff_route)
+                .or(status_route)
+                .or(metrics_route)
+                .recover(recover_invalid_query)
+                .or(default_route),
+        )
+        .recover(recover_unauthorized)
+        .recover(recover_forbidden)
+        .with(warp::log("capture_screen"))
+        .with(warp::log::custom(move |info| {
+            let state = app_handle.state::<RecorderState>();
+            state
+                .metrics
+                .observe_request(route_label(info.path()), info.status().as_u16());
+        }));
+
+    let result = warp::serve(routes).try_bind_with_graceful_shutdown(addr, async move {
+        shutdown.await.ok();
+        // 平滑关闭会等待所有连接结束，先通知长连接退出
+        shutdown_tx.send_replace(true);
+    });
+    match result {
+        Ok((addr, server)) => {
+            tracing::info!("HTTP server listening on {}", addr);
+            server.await;
+        }
+        Err(e) => tracing::error!("Failed to bind the HTTP server to {}: {}", addr, e),
+    }
+}
+
+/// 请求统计中的路由名，区域名称等路径参数不计入，避免标签无限增长
+fn route_label(path: &str) -> &'static str {
+    let first = path.trim_start_matches('/').split('/').next().unwrap_or("");
+    match first {
+        "capture_screen" | "capture_screen.json" if path.ends_with(".json") => {
+            "capture_screen.json"
+        }
+        "capture_screen" => "capture_screen",
+        "capture_window" => "capture_window",
+        "capture_monitor" => "capture_monitor",
+        "stream" => "stream",
+        "ws" => "ws",
+        "diff" => "diff",
+        "status" => "status",
+        "metrics" => "metrics",
+        _ => "other",
+    }
+}
+
+/// 一次截图的结果
+#[derive(Clone)]
+struct CapturedFrame {
+    /// 合并的请求共享同一份像素
+    image: Arc<RgbaImage>,
+    /// 像素内容的哈希
+    hash: u64,
+    rect: ScreenRect,
+    scale_factor: f64,
+    timestamp: u64,
+    sequence: u64,
+}
+
+/// 截图失败的原因
+#[derive(Debug, Clone)]
+enum CaptureFailure {
+    /// 区域窗口不存在
+    WindowNotFound,
+    /// 查询参数指定的区域无效
+    InvalidRegion(String),
+    /// 截图本身失败
+    Failed(String),
+    /// 排队的截图过多
+    Busy,
+}
+
+impl CaptureFailure {
+    fn into_response(self) -> Result<Response<Body>, warp::Rejection> {
+        match self {
+            CaptureFailure::InvalidRegion(message) => Ok(bad_request(message)),
+            CaptureFailure::Busy => {
+                let mut response = error_response(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    "Too many pending captures".to_string(),
+                );
+                response
+                    .headers_mut()
+                    .insert("Retry-After", RETRY_AFTER_SECONDS.into());
+                Ok(response)
+            }
+            CaptureFailure::WindowNotFound | CaptureFailure::Failed(_) => {
+                Err(warp::reject::not_found())
+            }
+        }
+    }
+}
+
+/// 截取主窗口，或 `region` 指定的命名区域窗口下方的屏幕；`query` 不为空时截取其指定的区域
+fn capture_frame(
+    app_handle: &AppHandle,
+    region: Option<&str>,
+    query: &CaptureQuery,
+) -> Result<CapturedFrame, CaptureFailure> {
+    let window =
+        regions::region_window(app_handle, region).ok_or(CaptureFailure::WindowNotFound)?;
+    let state = app_handle.state::<RecorderState>();
+    let backend = state.capture_backend();
+
+    let rect = if query.has_region() {
+        query
+            .resolve(&window)
+            .map_err(CaptureFailure::InvalidRegion)?
+    } else {
+        overlay_screen_rect(&window).map_err(|e| CaptureFailure::Failed(e.to_string()))?
+    };
+
+    let started = Instant::now();
+    let image = match capture_region(&*backend, &window, rect, query.options()) {
+        Ok(image) => {
+            let duration = started.elapsed();
+            state.metrics.observe_capture(duration);
+            state.capture_status.lock().unwrap().succeeded(duration);
+            image
+        }
+        Err(e) => {
+            state.metrics.capture_failed();
+            let message = e.to_string();
+            state.capture_status.lock().unwrap().failed(message.clone());
+            return Err(CaptureFailure::Failed(message));
+        }
+    };
+    let timestamp = unix_millis();
+    let sequence = state.capture_sequence.fetch_add(1, Ordering::Relaxed) + 1;
+    let scale_factor = scale_factor_at(&window, &rect);
+
+    Ok(CapturedFrame {
+        hash: content_hash(image.as_raw()),
+        image: Arc::new(image),
+        rect,
+        scale_factor,
+        timestamp,
+        sequence,
+    })
+}
+
+/// 毫秒级 Unix 时间戳
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// `rect` 左上角所在显示器的缩放比例，找不到时为 1
+fn scale_factor_at(window: &Window, rect: &ScreenRect) -> f64 {
+    monitor_geometries(window)
+        .ok()
+        .and_then(|monitors| {
+            monitor_at(&monitors, rect.x, rect.y).map(|monitor| monitor.scale_factor)
+        })
+        .unwrap_or(1.0)
+}
+
+/// 截图并在阻塞线程池中编码
+async fn capture_encoded(
+    app_handle: &AppHandle,
+    capturer: &Arc<Capturer>,
+    region: Option<&str>,
+    query: &CaptureQuery,
+    resize: ResizeOptions,
+    encode: EncodeOptions,
+) -> Result<(CapturedFrame, EncodedFrame), CaptureFailure> {
+    let captured = capturer.capture(app_handle, region, query).await?;
+    encode_captured(app_handle, captured, resize, encode).await
+}
+
+/// 在阻塞线程中缩放并编码已经截取的画面
+async fn encode_captured(
+    app_handle: &AppHandle,
+    mut captured: CapturedFrame,
+    resize: ResizeOptions,
+    encode: EncodeOptions,
+) -> Result<(CapturedFrame, EncodedFrame), CaptureFailure> {
+    let app_handle = app_handle.clone();
+    tokio::task::spawn_blocking(move || {
+        let state = app_handle.state::<RecorderState>();
+        let image = Arc::unwrap_or_clone(std::mem::take(&mut captured.image));
+        let frame = encode_frame(&state.metrics, image, &resize, &encode)
+            .map_err(|(_, message)| CaptureFailure::Failed(message))?;
+        Ok((captured, frame))
+    })
+    .await
+    .unwrap_or_else(|e| Err(CaptureFailure::Failed(e.to_string())))
+}
+
+/// 截取主窗口，或 `region` 指定的命名区域窗口；`query` 不为空时截取其指定的区域
+async fn handle_capture(
+    app_handle: AppHandle,
+    capturer: Arc<Capturer>,
+    region: Option<String>,
+    query: CaptureQuery,
+    resize: ResizeOptions,
+    encode: EncodeOptions,
+    if_none_match: Option<String>,
+) -> Result<Response<Body>, warp::Rejection> {
+    if let Err(message) = resize
+        .validate()
+        .and_then(|_| encode.validate())
+        .and_then(|_| wait::validate(&query))
+        .and_then(|_| query.settle().map_or(Ok(()), |settle| settle.validate()))
+    {
+        return Ok(bad_request(message));
+    }
+    let response_kind = query.response.unwrap_or_default();
+    if response_kind == ResponseKind::Mask {
+        return Ok(bad_request(
+            "as=mask is only supported by /diff".to_string(),
+        ));
+    }
+    // `data:` URL 中没有宽高，无法解码不带格式的像素
+    if response_kind == ResponseKind::Dataurl && encode.format() == OutputFormat::Rgba {
+        return Ok(bad_request(
+            "as=dataurl does not support format=rgba, use as=json instead".to_string(),
+        ));
+    }
+
+    let result = if query.wait_for_change {
+        let changed = wait::wait_for_change(
+            &app_handle,
+            &capturer,
+            region.as_deref(),
+            &query,
+            |hash| frame_etag(hash, &encode, response_kind),
+            if_none_match.as_deref(),
+        )
+        .await;
+        match changed {
+            Ok(Waited::Changed(captured)) => Ok(captured),
+            Ok(Waited::Unchanged { hash }) => {
+                let etag = frame_etag(hash, &encode, response_kind);
+                return Ok(vary_accept(not_modified(&etag), &encode));
+            }
+            Err(failure) => Err(failure),
+        }
+    } else {
+        capturer
+            .capture(&app_handle, region.as_deref(), &query)
+            .await
+    };
+    let result = match (result, query.settle()) {
+        (Ok(captured), Some(settle)) => {
+            let region = region.as_deref();
+            wait::wait_for_settle(&app_handle, &capturer, region, &query, captured, &settle).await
+        }
+        (result, _) => result,
+    };
+    let captured = match result {
+        Ok(captured) => captured,
+        Err(failure) => return failure.into_response(),
+    };
+
+    let etag = frame_etag(captured.hash, &encode, response_kind);
+    if !query.wait_for_change && etag_matches(if_none_match.as_deref(), &etag) {
+        return Ok(vary_accept(not_modified(&etag), &encode));
+    }
+    let image = Arc::unwrap_or_clone(captured.image);
+
+    let state = app_handle.state::<RecorderState>();
+    let metrics = &state.metrics;
+    let response = match response_kind {
+        ResponseKind::Image => image_response(metrics, image, &resize, &encode),
+        ResponseKind::Mask => unreachable!("as=mask is rejected before capturing"),
+        ResponseKind::Dataurl => {
+            let frame = match encode_frame(metrics, image, &resize, &encode) {
+                Ok(frame) => frame,
+                Err((status, message)) => return Ok(error_response(status, message)),
+            };
+            metrics.add_served_bytes(frame.bytes.len());
+            Ok(text_response(
+                "text/plain; charset=utf-8",
+                format!(
+                    "data:{};base64,{}",
+                    frame.format.content_type(),
+                    base64::encode(&frame.bytes)
+                ),
+            ))
+        }
+        ResponseKind::Json => {
+            let frame = match encode_frame(metrics, image, &resize, &encode) {
+                Ok(frame) => frame,
+                Err((status, message)) => return Ok(error_response(status, message)),
+            };
+            metrics.add_served_bytes(frame.bytes.len());
+            let body = CaptureJson {
+                image: base64::encode(&frame.bytes),
+                content_type: frame.format.content_type(),
+                width: frame.width,
+                height: frame.height,
+                scale_factor: captured.scale_factor,
+                rect: captured.rect,
+                timestamp: captured.timestamp,
+                sequence: captured.sequence,
+            };
+            Ok(text_response(
+                "application/json",
+                serde_json::to_string(&body).unwrap(),
+            ))
+        }
+    };
+    with_etag(response, &etag).map(|response| vary_accept(response, &encode))
+}
+
+/// 截取按标题、进程名或 id 指定的应用窗口
+async fn handle_capture_window(
+    app_handle: AppHandle,
+    capturer: Arc<Capturer>,
+    selector: WindowSelector,
+    resize: ResizeOptions,
+    encode: EncodeOptions,
+    if_none_match: Option<String>,
+) -> Result<Response<Body>, warp::Rejection> {
+    if selector.is_empty() {
+        return Ok(bad_request(
+            "One of title, app, pid or id is required".to_string(),
+        ));
+    }
+    if let Err(message) = resize.validate().and_then(|_| encode.validate()) {
+        return Ok(bad_request(message));
+    }
+
+    let result = capturer
+        .exclusive(move || capture_window(&selector))
+        .await
+        .unwrap_or_else(|message| Err(XCapError::new(message)));
+
+    let state = app_handle.state::<RecorderState>();
+    match result {
+        Ok(Some(image)) => {
+            cached_image_response(&state.metrics, image, &resize, &encode, if_none_match)
+        }
+        Ok(None) => Err(warp::reject::not_found()),
+        Err(e) => {
+            tracing::error!("capture_window failed: {:?}", e);
+            Err(warp::reject::not_found())
+        }
+    }
+}
+
+/// 截取整个显示器，`key` 为序号或名称，截图期间隐藏所有覆盖窗口
+async fn handle_capture_monitor(
+    app_handle: AppHandle,
+    capturer: Arc<Capturer>,
+    key: String,
+    resize: ResizeOptions,
+    encode: EncodeOptions,
+    if_none_match: Option<String>,
+) -> Result<Response<Body>, warp::Rejection> {
+    if let Err(message) = resize.validate().and_then(|_| encode.validate()) {
+        return Ok(bad_request(message));
+    }
+
+    let capture_handle = app_handle.clone();
+    let result = capturer
+        .exclusive(move || {
+            let Some(rect) = find_monitor_rect(&key)? else {
+                return Ok(None);
+            };
+            let backend = capture_handle.state::<RecorderState>().capture_backend();
+            let windows = regions::overlay_windows(&capture_handle);
+            capture_region_hiding(&*backend, &windows, rect, CaptureOptions::default()).map(Some)
+        })
+        .await
+        .unwrap_or_else(|message| Err(XCapError::new(message)));
+
+    let state = app_handle.state::<RecorderState>();
+    match result {
+        Ok(Some(image)) => {
+            cached_image_response(&state.metrics, image, &resize, &encode, if_none_match)
+        }
+        Ok(None) => Err(warp::reject::not_found()),
+        Err(e) => {
+            tracing::error!("capture_monitor failed: {:?}", e);
+            Err(warp::reject::not_found())
+        }
+    }
+}
+
+/// 缩放并编码后的截图
+struct EncodedFrame {
+    bytes: Vec<u8>,
+    format: OutputFormat,
+    width: u32,
+    height: u32,
+}
+
+/// 缩放并按 `encode` 编码截图，失败时返回状态码和错误信息
+fn encode_frame(
+    metrics: &Metrics,
+    image: RgbaImage,
+    resize: &ResizeOptions,
+    encode: &EncodeOptions,
+) -> Result<EncodedFrame, (StatusCode, String)> {
+    let started = Instant::now();
+    let image =
+        resize_image(image, resize).map_err(|message| (StatusCode::BAD_REQUEST, message))?;
+    let bytes = encode_image(&image, encode).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to encode image: {}", e),
+        )
+    })?;
+    metrics.observe_encode(started.elapsed());
+    Ok(EncodedFrame {
+        bytes,
+        format: encode.format(),
+        width: image.width(),
+        height: image.height(),
+    })
+}
+
+/// `ETag` 由像素内容、输出格式和响应形式决定；缩放参数在 URL 中，不同的 URL 各自缓存
+fn frame_etag(hash: u64, encode: &EncodeOptions, response: ResponseKind) -> String {
+    let mut hasher = DefaultHasher::new();
+    hash.hash(&mut hasher);
+    encode.format().hash(&mut hasher);
+    encode.quality.hash(&mut hasher);
+    response.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// `If-None-Match` 是否包含 `etag`，按弱比较处理 `W/` 前缀，`*` 匹配任何截图
+fn etag_matches(if_none_match: Option<&str>, etag: &str) -> bool {
+    let Some(if_none_match) = if_none_match else {
+        return false;
+    };
+    if_none_match.split(',').map(str::trim).any(|candidate| {
+        candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+    })
+}
+
+fn not_modified(etag: &str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_MODIFIED)
+        .header("ETag", etag)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// 为成功的响应加上 `ETag`，错误响应不缓存
+fn with_etag(
+    response: Result<Response<Body>, warp::Rejection>,
+    etag: &str,
+) -> Result<Response<Body>, warp::Rejection> {
+    response.map(|mut response| {
+        if response.status().is_success() {
+            response.headers_mut().insert("ETag", etag.parse().unwrap());
+        }
+        response
+    })
+}
+
+/// 带 `ETag` 的图片响应，`If-None-Match` 匹配时返回 304 而不编码图片
+fn cached_image_response(
+    metrics: &Metrics,
+    image: RgbaImage,
+    resize: &ResizeOptions,
+    encode: &EncodeOptions,
+    if_none_match: Option<String>,
+) -> Result<Response<Body>, warp::Rejection> {
+    let etag = frame_etag(content_hash(image.as_raw()), encode, ResponseKind::Image);
+    if etag_matches(if_none_match.as_deref(), &etag) {
+        return Ok(vary_accept(not_modified(&etag), encode));
+    }
+    with_etag(image_response(metrics, image, resize, encode), &etag)
+        .map(|response| vary_accept(response, encode))
+}
+
+/// 格式由 `Accept` 请求头协商时，缓存需要按 `Accept` 区分响应
+fn vary_accept(mut response: Response<Body>, encode: &EncodeOptions) -> Response<Body> {
+    if encode.negotiated {
+        response
+            .headers_mut()
+            .insert("Vary", "Accept".parse().unwrap());
+    }
+    response
+}
+
+/// 图片响应，`rgba` 格式通过响应头返回宽高
+fn image_response(
+    metrics: &Metrics,
+    image: RgbaImage,
+    resize: &ResizeOptions,
+    encode: &EncodeOptions,
+) -> Result<Response<Body>, warp::Rejection> {
+    let frame = match encode_frame(metrics, image, resize, encode) {
+        Ok(frame) => frame,
+        Err((status, message)) => return Ok(error_response(status, message)),
+    };
+
+    metrics.add_served_bytes(frame.bytes.len());
+    let mut builder = Response::builder().header("Content-Type", frame.format.content_type());
+    if frame.format == OutputFormat::Rgba {
+        builder = builder
+            .header("X-Image-Width", frame.width)
+            .header("X-Image-Height", frame.height);
+    }
+    Ok(builder.body(Body::from(frame.bytes)).unwrap())
+}
+
+/// 查询参数中的开关，接受 `1`/`0`、`true`/`false`、`yes`/`no`，以及不带值的 `?include_cursor`；
+/// WebSocket 控制消息中也可以是 JSON 布尔值
+fn deserialize_flag<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Flag {
+        Bool(bool),
+        Text(String),
+    }
+
+    let value = match Flag::deserialize(deserializer)? {
+        Flag::Bool(value) => return Ok(value),
+        Flag::Text(value) => value,
+    };
+    match value.to_ascii_lowercase().as_str() {
+        "" | "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(serde::de::Error::custom(format!(
+            "invalid flag value {:?}",
+            value
+        ))),
+    }
+}
+
+fn bad_request(message: String) -> Response<Body> {
+    error_response(StatusCode::BAD_REQUEST, message)
+}
+
+fn error_response(status: StatusCode, message: String) -> Response<Body> {
+    text_response_with_status(status, "text/plain; charset=utf-8", message)
+}
+
+fn text_response(content_type: &str, body: String) -> Response<Body> {
+    text_response_with_status(StatusCode::OK, content_type, body)
+}
+
+fn text_response_with_status(
+    status: StatusCode,
+    content_type: &str,
+    body: String,
+) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", content_type)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[derive(Debug)]
+struct InvalidQuery(String);
+
+impl warp::reject::Reject for InvalidQuery {}
+
+/// 同 `warp::query`，但保留 serde 的错误信息，由 `recover_invalid_query` 返回 400
+fn query<T: DeserializeOwned + Send + 'static>(
+) -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone {
+    warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+        .and_then(|raw: String| async move {
+            serde_urlencoded::from_str::<T>(&raw)
+                .map_err(|e| warp::reject::custom(InvalidQuery(e.to_string())))
+        })
+}
+
+/// 无效的查询参数返回 400，而不是落到默认路由的 404
+async fn recover_invalid_query(
+    rejection: warp::Rejection,
+) -> Result<Response<Body>, warp::Rejection> {
+    match rejection.find::<InvalidQuery>() {
+        Some(InvalidQuery(message)) => Ok(bad_request(format!("Invalid query: {}", message))),
+        None => Err(rejection),
+    }
+}
+
+/// `format`、`quality` 查询参数，未指定 `format` 时按 `Accept` 请求头协商
+fn encode_options() -> impl Filter<Extract = (EncodeOptions,), Error = warp::Rejection> + Clone {
+    query::<EncodeOptions>()
+        .and(warp::header::optional::<String>("accept"))
+        .map(|options: EncodeOptions, accept: Option<String>| options.negotiate(accept.as_deref()))
+}
+
+fn if_none_match() -> impl Filter<Extract = (Option<String>,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("if-none-match")
+}
+
+/// 服务停止的通知，`/stream` 等长连接据此退出，否则服务停止后它们仍在截图和发送
+#[derive(Debug, Clone)]
+struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// 服务开始停止时返回，通知的发送端被丢弃同样视为停止
+    async fn wait(&mut self) {
+        self.0.wait_for(|stopping| *stopping).await.ok();
+    }
+}
+
+fn with_shutdown(
+    shutdown: Shutdown,
+) -> impl Filter<Extract = (Shutdown,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || shutdown.clone())
+}
+
+fn with_capturer(
+    capturer: Arc<Capturer>,
+) -> impl Filter<Extract = (Arc<Capturer>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || capturer.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ETAG: &str = "\"0123456789abcdef\"";
+
+    #[test]
+    fn etag_is_quoted_and_depends_on_encoding() {
+        let png = frame_etag(1, &EncodeOptions::default(), ResponseKind::Image);
+        assert!(png.starts_with('"') && png.ends_with('"'));
+        assert_eq!(
+            png,
+            frame_etag(1, &EncodeOptions::default(), ResponseKind::Image)
+        );
+
+        let jpeg = EncodeOptions {
+            format: Some(OutputFormat::Jpeg),
+            ..Default::default()
+        };
+        assert_ne!(
+            png,
+            frame_etag(2, &EncodeOptions::default(), ResponseKind::Image)
+        );
+        assert_ne!(png, frame_etag(1, &jpeg, ResponseKind::Image));
+        assert_ne!(
+            png,
+            frame_etag(1, &EncodeOptions::default(), ResponseKind::Json)
+        );
+    }
+
+    #[test]
+    fn missing_header_never_matches() {
+        assert!(!etag_matches(None, ETAG));
+        assert!(!etag_matches(Some(""), ETAG));
+    }
+
+    #[test]
+    fn star_matches_any_etag() {
+        assert!(etag_matches(Some("*"), ETAG));
+        assert!(etag_matches(Some(" * "), ETAG));
+        assert!(etag_matches(Some("\"other\", *"), ETAG));
+    }
+
+    #[test]
+    fn weak_tags_use_weak_comparison() {
+        assert!(etag_matches(Some("W/\"0123456789abcdef\""), ETAG));
+        assert!(!etag_matches(Some("W/\"fedcba9876543210\""), ETAG));
+    }
+
+    #[test]
+    fn any_tag_in_a_list_matches() {
+        assert!(etag_matches(Some("\"other\", \"0123456789abcdef\""), ETAG));
+        assert!(etag_matches(
+            Some("\"other\",W/\"0123456789abcdef\" , \"more\""),
+            ETAG
+        ));
+        assert!(!etag_matches(Some("\"other\", \"more\""), ETAG));
+    }
+
+    #[test]
+    fn quotes_are_part_of_the_tag() {
+        assert!(etag_matches(Some(ETAG), ETAG));
+        assert!(!etag_matches(Some("0123456789abcdef"), ETAG));
+        assert!(!etag_matches(Some("W/0123456789abcdef"), ETAG));
+        assert!(!etag_matches(Some("\"0123456789ABCDEF\""), ETAG));
+    }
+}
@@ -0,0 +1,393 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+//! 合并并发的截图请求
+//!
+//! 截图时需要隐藏覆盖窗口，多个截图同时进行会互相干扰，因此同一时间只进行一次截图。
+//! 截图进行中时，截取相同区域的请求等待并共享这一次的结果，而不是各自再截一次。
+
+use super::{capture_frame, CaptureFailure, CaptureQuery, CapturedFrame, RegionOrigin};
+use crate::{config, RecorderState};
+use image::RgbaImage;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager};
+use tokio::sync::watch;
+
+const DEFAULT_QUEUE_LIMIT: usize = 16;
+/// 最多为几组截图参数保留上一次截图的像素，作为 `wait_for_change` 和 `/diff` 比较的基准；
+/// 每组都是一整张截图，数量不宜过多
+const HISTORY_KEYS: usize = 8;
+
+type SharedResult = Arc<Result<CapturedFrame, CaptureFailure>>;
+
+/// 决定两个请求能否共享同一次截图：区域窗口和截取参数都相同
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CaptureKey {
+    region: Option<String>,
+    x: Option<i32>,
+    y: Option<i32>,
+    w: Option<u32>,
+    h: Option<u32>,
+    monitor: Option<usize>,
+    origin: Option<RegionOrigin>,
+    include_cursor: bool,
+}
+
+impl CaptureKey {
+    fn new(region: Option<&str>, query: &CaptureQuery) -> Self {
+        CaptureKey {
+            region: region.map(str::to_string),
+            x: query.x,
+            y: query.y,
+            w: query.w,
+            h: query.h,
+            monitor: query.monitor,
+            origin: query.origin,
+            include_cursor: query.include_cursor,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Flights {
+    /// 进行中或排队中的截图，值为等待结果的通道
+    pending: HashMap<CaptureKey, watch::Receiver<Option<SharedResult>>>,
+    /// 最近一次成功的截图，只在 `max_age` 大于 0 时保存
+    recent: HashMap<CaptureKey, (Instant, CapturedFrame)>,
+    history: History,
+    /// 正在等待截图结果的请求数，共享同一次截图的请求分别计数
+    waiting: usize,
+}
+
+/// 每组截图参数上一次成功的截图
+#[derive(Default)]
+struct History {
+    frames: HashMap<CaptureKey, HistoryFrame>,
+    /// 递增的序号，用于找出最近的截图
+    next_sequence: u64,
+}
+
+struct HistoryFrame {
+    sequence: u64,
+    hash: u64,
+    image: Arc<RgbaImage>,
+}
+
+impl History {
+    /// 替换 `key` 上一次的截图；参数超过 `HISTORY_KEYS` 组时丢弃最久没有截图的一组
+    fn record(&mut self, key: CaptureKey, hash: u64, image: Arc<RgbaImage>) {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        self.frames.insert(
+            key,
+            HistoryFrame {
+                sequence,
+                hash,
+                image,
+            },
+        );
+        if self.frames.len() > HISTORY_KEYS {
+            let oldest = self
+                .frames
+                .iter()
+                .min_by_key(|(_, frame)| frame.sequence)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.frames.remove(&oldest);
+            }
+        }
+    }
+
+    fn previous(&self, key: &CaptureKey) -> Option<(u64, Arc<RgbaImage>)> {
+        self.frames
+            .get(key)
+            .map(|frame| (frame.hash, frame.image.clone()))
+    }
+
+    /// 哈希满足 `matches` 的截图中最近的一张
+    fn find(&self, matches: impl Fn(u64) -> bool) -> Option<(u64, Arc<RgbaImage>)> {
+        self.frames
+            .values()
+            .filter(|frame| matches(frame.hash))
+            .max_by_key(|frame| frame.sequence)
+            .map(|frame| (frame.hash, frame.image.clone()))
+    }
+}
+
+/// HTTP 接口和 Tauri 命令共用，保证覆盖窗口的隐藏与恢复不会交错
+pub struct Capturer {
+    /// 在此时间内的截图直接复用，0 表示不复用
+    max_age: Duration,
+    /// 等待截图结果的请求数上限，超过时返回 429
+    queue_limit: usize,
+    /// 同一时间只进行一次截图
+    capture_lock: tokio::sync::Mutex<()>,
+    flights: Mutex<Flights>,
+}
+
+impl Capturer {
+    /// 从 `--capture-max-age-ms` / `COMFY_CAPTURER_MAX_AGE_MS` 和
+    /// `--capture-queue-limit` / `COMFY_CAPTURER_QUEUE_LIMIT` 读取配置
+    pub fn from_config() -> Self {
+        let max_age_ms = config::option("capture-max-age-ms", "COMFY_CAPTURER_MAX_AGE_MS")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0);
+        let queue_limit = config::option("capture-queue-limit", "COMFY_CAPTURER_QUEUE_LIMIT")
+            .and_then(|value| value.parse().ok())
+            .filter(|limit| *limit > 0)
+            .unwrap_or(DEFAULT_QUEUE_LIMIT);
+        Capturer {
+            max_age: Duration::from_millis(max_age_ms),
+            queue_limit,
+            capture_lock: tokio::sync::Mutex::new(()),
+            flights: Mutex::new(Flights::default()),
+        }
+    }
+
+    /// 截取 `region` 窗口下 `query` 指定的区域；相同的截图正在进行时等待并共享它的结果
+    pub(super) async fn capture(
+        self: &Arc<Self>,
+        app_handle: &AppHandle,
+        region: Option<&str>,
+        query: &CaptureQuery,
+    ) -> Result<CapturedFrame, CaptureFailure> {
+        self.capture_with(app_handle, region, query, true).await
+    }
+
+    /// 同 `capture`，但不复用 `max_age` 内的截图，用于需要逐帧比较的场景
+    pub(super) async fn capture_fresh(
+        self: &Arc<Self>,
+        app_handle: &AppHandle,
+        region: Option<&str>,
+        query: &CaptureQuery,
+    ) -> Result<CapturedFrame, CaptureFailure> {
+        self.capture_with(app_handle, region, query, false).await
+    }
+
+    async fn capture_with(
+        self: &Arc<Self>,
+        app_handle: &AppHandle,
+        region: Option<&str>,
+        query: &CaptureQuery,
+        reuse_recent: bool,
+    ) -> Result<CapturedFrame, CaptureFailure> {
+        let key = CaptureKey::new(region, query);
+        let mut receiver = {
+            let mut flights = self.flights.lock().unwrap();
+            if let Some((captured_at, frame)) = flights.recent.get(&key) {
+                if reuse_recent && captured_at.elapsed() <= self.max_age {
+                    return Ok(frame.clone());
+                }
+            }
+
+            if flights.waiting >= self.queue_limit {
+                let state = app_handle.state::<RecorderState>();
+                state.metrics.rejected();
+                return Err(CaptureFailure::Busy);
+            }
+            flights.waiting += 1;
+
+            match flights.pending.get(&key) {
+                Some(receiver) => receiver.clone(),
+                None => {
+                    let (sender, receiver) = watch::channel(None);
+                    flights.pending.insert(key.clone(), receiver.clone());
+                    // 截图在独立的任务中完成，发起请求的客户端断开也不影响其他等待者
+                    tokio::spawn(self.clone().run(
+                        key,
+                        app_handle.clone(),
+                        region.map(str::to_string),
+                        query.clone(),
+                        sender,
+                    ));
+                    receiver
+                }
+            }
+        };
+
+        // 请求被取消时也要减去计数
+        let _waiting = scopeguard::guard((), |_| self.flights.lock().unwrap().waiting -= 1);
+        let result = match receiver.wait_for(Option::is_some).await {
+            Ok(result) => result.as_deref().unwrap().clone(),
+            Err(_) => Err(CaptureFailure::Failed("Capture task stopped".to_string())),
+        };
+        result
+    }
+
+    /// `region`、`query` 上一次截图的哈希和像素，其他区域的截图不会挤掉它
+    pub(super) fn previous(
+        &self,
+        region: Option<&str>,
+        query: &CaptureQuery,
+    ) -> Option<(u64, Arc<RgbaImage>)> {
+        let key = CaptureKey::new(region, query);
+        self.flights.lock().unwrap().history.previous(&key)
+    }
+
+    /// 哈希满足 `matches` 的最近一次截图
+    pub(super) fn find(&self, matches: impl Fn(u64) -> bool) -> Option<(u64, Arc<RgbaImage>)> {
+        self.flights.lock().unwrap().history.find(matches)
+    }
+
+    /// 不合并的截图也需要与其他截图错开，例如截取整个显示器
+    pub async fn exclusive<T: Send + 'static>(
+        &self,
+        capture: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T, String> {
+        let _guard = self.capture_lock.lock().await;
+        tokio::task::spawn_blocking(capture)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn run(
+        self: Arc<Self>,
+        key: CaptureKey,
+        app_handle: AppHandle,
+        region: Option<String>,
+        query: CaptureQuery,
+        sender: watch::Sender<Option<SharedResult>>,
+    ) {
+        let result = self
+            .exclusive(move || capture_frame(&app_handle, region.as_deref(), &query))
+            .await
+            .unwrap_or_else(|message| Err(CaptureFailure::Failed(message)));
+
+        {
+            let mut flights = self.flights.lock().unwrap();
+            flights.pending.remove(&key);
+            if let Ok(frame) = &result {
+                flights
+                    .history
+                    .record(key.clone(), frame.hash, frame.image.clone());
+            }
+            if !self.max_age.is_zero() {
+                let max_age = self.max_age;
+                flights
+                    .recent
+                    .retain(|_, (captured_at, _)| captured_at.elapsed() <= max_age);
+                if let Ok(frame) = &result {
+                    flights.recent.insert(key, (Instant::now(), frame.clone()));
+                }
+            }
+        }
+        sender.send(Some(Arc::new(result))).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn key(region: &str) -> CaptureKey {
+        CaptureKey {
+            region: Some(region.to_string()),
+            x: None,
+            y: None,
+            w: None,
+            h: None,
+            monitor: None,
+            origin: None,
+            include_cursor: false,
+        }
+    }
+
+    fn image(value: u8) -> Arc<RgbaImage> {
+        Arc::new(RgbaImage::from_pixel(1, 1, Rgba([value, 0, 0, 255])))
+    }
+
+    fn hash_of(history: &History, region: &str) -> Option<u64> {
+        history.previous(&key(region)).map(|(hash, _)| hash)
+    }
+
+    #[test]
+    fn each_key_keeps_its_last_frame() {
+        let mut history = History::default();
+        assert_eq!(hash_of(&history, "a"), None);
+
+        history.record(key("a"), 1, image(1));
+        history.record(key("a"), 2, image(2));
+        history.record(key("b"), 3, image(3));
+
+        let (hash, image) = history.previous(&key("a")).unwrap();
+        assert_eq!(hash, 2);
+        assert_eq!(image.get_pixel(0, 0).0[0], 2);
+        assert_eq!(hash_of(&history, "b"), Some(3));
+        assert_eq!(history.frames.len(), 2);
+    }
+
+    #[test]
+    fn frequent_captures_do_not_evict_other_keys() {
+        let mut history = History::default();
+        history.record(key("a"), 1, image(1));
+        for hash in 0..100 {
+            history.record(key("stream"), 100 + hash, image(0));
+        }
+        assert_eq!(hash_of(&history, "a"), Some(1));
+    }
+
+    #[test]
+    fn diff_baseline_survives_captures_of_other_keys() {
+        // `/diff` 先取 "a" 上一次的截图，再截取新的一帧与之比较
+        let mut history = History::default();
+        let first = RgbaImage::from_fn(4, 4, |x, _| Rgba([x as u8, 0, 0, 255]));
+        history.record(key("a"), 1, Arc::new(first.clone()));
+
+        // 两帧之间有一个推流区域不停截图，另外几个区域各截了一次
+        for index in 0..HISTORY_KEYS - 1 {
+            for hash in 0..10 {
+                history.record(key(&format!("other{}", index)), hash, image(0));
+            }
+        }
+
+        let (hash, baseline) = history.previous(&key("a")).unwrap();
+        assert_eq!(hash, 1);
+        assert_eq!(*baseline, first);
+
+        let mut second = first.clone();
+        second.put_pixel(0, 0, Rgba([255, 255, 255, 255]));
+        let stats = crate::diff::compare(&baseline, &second);
+        assert_eq!(stats.changed_pixels, 1);
+        assert_eq!(stats.total_pixels, 16);
+    }
+
+    #[test]
+    fn evicts_the_least_recently_captured_key() {
+        let mut history = History::default();
+        for index in 0..HISTORY_KEYS as u64 {
+            history.record(key(&index.to_string()), index, image(0));
+        }
+        // 重新截取 "0" 之后最久没有截图的是 "1"
+        history.record(key("0"), 100, image(0));
+        history.record(key("new"), 200, image(0));
+
+        assert_eq!(history.frames.len(), HISTORY_KEYS);
+        assert_eq!(hash_of(&history, "1"), None);
+        assert_eq!(hash_of(&history, "0"), Some(100));
+        assert_eq!(hash_of(&history, "new"), Some(200));
+    }
+
+    #[test]
+    fn find_returns_the_most_recent_match() {
+        let mut history = History::default();
+        history.record(key("a"), 7, image(1));
+        history.record(key("b"), 7, image(2));
+        history.record(key("c"), 8, image(3));
+
+        let (hash, image) = history.find(|hash| hash == 7).unwrap();
+        assert_eq!(hash, 7);
+        assert_eq!(image.get_pixel(0, 0).0[0], 2);
+        assert!(history.find(|hash| hash == 9).is_none());
+    }
+}
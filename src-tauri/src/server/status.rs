@@ -0,0 +1,94 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+//! `/status`：不截图即可查询截图服务的状态
+
+use super::{scale_factor_at, text_response, unix_millis};
+use crate::capture::{overlay_screen_rect, ScreenRect};
+use crate::{regions, RecorderState};
+use serde::Serialize;
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+use warp::http::Response;
+use warp::hyper::Body;
+
+/// HTTP 截图最近一次的耗时与错误，保存在 `RecorderState` 中
+#[derive(Debug, Default)]
+pub struct CaptureStatus {
+    last_duration: Option<Duration>,
+    last_error: Option<LastError>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct LastError {
+    message: String,
+    /// 出错的时间，毫秒级 Unix 时间戳
+    timestamp: u64,
+}
+
+impl CaptureStatus {
+    pub(super) fn succeeded(&mut self, duration: Duration) {
+        self.last_duration = Some(duration);
+    }
+
+    /// 之后的截图成功时仍保留错误，客户端可以根据时间戳判断它是否已经过时
+    pub(super) fn failed(&mut self, message: String) {
+        self.last_error = Some(LastError {
+            message,
+            timestamp: unix_millis(),
+        });
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct StatusJson {
+    version: &'static str,
+    port: u16,
+    bind_address: String,
+    /// 覆盖窗口截取的屏幕区域，物理像素；窗口不存在时为 `null`
+    region: Option<ScreenRect>,
+    scale_factor: Option<f64>,
+    is_pin: bool,
+    uptime_ms: u64,
+    /// 本次运行中 HTTP 截图的次数
+    capture_count: u64,
+    last_capture_ms: Option<f64>,
+    last_error: Option<LastError>,
+}
+
+pub(super) fn handle_status(app_handle: &AppHandle, addr: SocketAddr) -> Response<Body> {
+    let state = app_handle.state::<RecorderState>();
+    let region = regions::region_window(app_handle, None)
+        .and_then(|window| Some((overlay_screen_rect(&window).ok()?, window)));
+    let scale_factor = region
+        .as_ref()
+        .map(|(rect, window)| scale_factor_at(window, rect));
+    let (last_duration, last_error) = {
+        let status = state.capture_status.lock().unwrap();
+        (status.last_duration, status.last_error.clone())
+    };
+
+    let body = StatusJson {
+        version: env!("CARGO_PKG_VERSION"),
+        port: addr.port(),
+        bind_address: addr.ip().to_string(),
+        region: region.map(|(rect, _)| rect),
+        scale_factor,
+        is_pin: state.is_pin.load(Ordering::SeqCst),
+        uptime_ms: state.started_at.elapsed().as_millis() as u64,
+        capture_count: state.capture_sequence.load(Ordering::Relaxed),
+        last_capture_ms: last_duration.map(|duration| duration.as_secs_f64() * 1000.0),
+        last_error,
+    };
+    text_response("application/json", serde_json::to_string(&body).unwrap())
+}
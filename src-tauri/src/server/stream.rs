@@ -0,0 +1,174 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+//! `/stream`：multipart/x-mixed-replace 的 MJPEG 流
+//!
+//! 每个连接有一个截图任务和一个发送任务，二者之间用 `watch` 通道只保留最新的一帧，
+//! 客户端读得慢时旧帧直接被覆盖，不会在服务端排队。服务停止时两个任务都会退出。
+
+use super::{bad_request, capture_encoded, CaptureFailure, CaptureQuery, Capturer, Shutdown};
+use crate::encode::{EncodeOptions, OutputFormat};
+use crate::regions;
+use crate::resize::ResizeOptions;
+use crate::RecorderState;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
+use warp::http::Response;
+use warp::hyper::body::Bytes;
+use warp::hyper::Body;
+
+const BOUNDARY: &str = "frame";
+const DEFAULT_FPS: f64 = 5.0;
+const MAX_FPS: f64 = 30.0;
+
+/// `/stream` 特有的查询参数，区域和缩放参数与 `/capture_screen` 相同
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct StreamQuery {
+    /// 每秒帧数，默认 5，最大 30
+    fps: Option<f64>,
+    /// JPEG 质量，1 到 100
+    quality: Option<u8>,
+}
+
+pub(super) async fn handle_stream(
+    app_handle: AppHandle,
+    capturer: Arc<Capturer>,
+    mut shutdown: Shutdown,
+    region: Option<String>,
+    query: CaptureQuery,
+    resize: ResizeOptions,
+    stream: StreamQuery,
+) -> Result<Response<Body>, warp::Rejection> {
+    let fps = stream.fps.unwrap_or(DEFAULT_FPS);
+    if !fps.is_finite() || fps <= 0.0 || fps > MAX_FPS {
+        return Ok(bad_request(format!(
+            "fps must be greater than 0 and at most {}",
+            MAX_FPS
+        )));
+    }
+    let encode = EncodeOptions {
+        format: Some(OutputFormat::Jpeg),
+        quality: stream.quality,
+        ..Default::default()
+    };
+    if let Err(message) = resize.validate().and_then(|_| encode.validate()) {
+        return Ok(bad_request(message));
+    }
+    if regions::region_window(&app_handle, region.as_deref()).is_none() {
+        return Err(warp::reject::not_found());
+    }
+
+    // 值为 multipart 的一段和其中图片的字节数
+    let (frames_tx, mut frames_rx) = watch::channel(None::<(Bytes, usize)>);
+    let sender_handle = app_handle.clone();
+
+    // 截图任务：所有接收端都断开或服务停止后退出
+    let mut capture_shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / fps));
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            tokio::select! {
+                _ = capture_shutdown.wait() => break,
+                _ = ticker.tick() => {}
+            }
+            if frames_tx.is_closed() {
+                break;
+            }
+
+            let result = tokio::select! {
+                _ = capture_shutdown.wait() => break,
+                result = capture_encoded(
+                    &app_handle,
+                    &capturer,
+                    region.as_deref(),
+                    &query,
+                    resize,
+                    encode,
+                ) => result,
+            };
+
+            match result {
+                Ok((_, frame)) => {
+                    let part = (multipart_part(&frame.bytes), frame.bytes.len());
+                    if frames_tx.send(Some(part)).is_err() {
+                        break;
+                    }
+                }
+                Err(CaptureFailure::Failed(message)) => {
+                    tracing::error!("stream capture failed: {}", message);
+                }
+                // 其他请求占满了截图队列，跳过这一帧
+                Err(CaptureFailure::Busy) => {}
+                Err(CaptureFailure::WindowNotFound) => break,
+                Err(CaptureFailure::InvalidRegion(message)) => {
+                    tracing::warn!("stream stopped: {}", message);
+                    break;
+                }
+            }
+        }
+    });
+
+    // 发送任务：客户端断开时 `send_data` 返回错误，丢弃接收端后截图任务随之退出；
+    // 服务停止时丢弃 `sender` 结束响应，平滑关闭不必等待这个连接
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = shutdown.wait() => break,
+                changed = frames_rx.changed() => {
+                    if changed.is_err() {
+                        break;
+                    }
+                }
+            }
+            let part = frames_rx.borrow_and_update().clone();
+            if let Some((part, image_bytes)) = part {
+                // 客户端不读数据时 `send_data` 会一直等待
+                let sent = tokio::select! {
+                    _ = shutdown.wait() => break,
+                    sent = sender.send_data(part) => sent,
+                };
+                if sent.is_err() {
+                    break;
+                }
+                // 被新的一帧替换掉的帧没有发送，不计入
+                let state = sender_handle.state::<RecorderState>();
+                state.metrics.add_served_bytes(image_bytes);
+            }
+        }
+    });
+
+    Ok(Response::builder()
+        .header(
+            "Content-Type",
+            format!("multipart/x-mixed-replace; boundary={}", BOUNDARY),
+        )
+        .header("Cache-Control", "no-cache")
+        .body(body)
+        .unwrap())
+}
+
+fn multipart_part(jpeg: &[u8]) -> Bytes {
+    let mut part = format!(
+        "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+        BOUNDARY,
+        jpeg.len()
+    )
+    .into_bytes();
+    part.extend_from_slice(jpeg);
+    part.extend_from_slice(b"\r\n");
+    Bytes::from(part)
+}
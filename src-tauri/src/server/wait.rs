@@ -0,0 +1,124 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+//! 需要反复截图的请求参数
+//!
+//! `wait_for_change`：长轮询，画面变化超过阈值时才返回截图。比较的基准优先取客户端
+//! `If-None-Match` 对应的截图，其次是同一区域的上一次截图，都没有时取第一次采样的截图。
+//!
+//! `settle_ms`：返回第一帧连续 `settle_ms` 没有变化的截图。
+
+use super::{etag_matches, CaptureFailure, CaptureQuery, CapturedFrame, Capturer};
+use crate::diff;
+use crate::settle::{SettleOptions, Settler};
+use image::RgbaImage;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::AppHandle;
+use tokio::time::Instant;
+
+/// 两次采样之间的间隔
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+const MAX_TIMEOUT_MS: u64 = 300_000;
+
+pub(super) enum Waited {
+    /// 变化超过阈值的截图
+    Changed(CapturedFrame),
+    /// 超时前没有变化，`hash` 为基准截图的哈希
+    Unchanged { hash: u64 },
+}
+
+pub(super) fn validate(query: &CaptureQuery) -> Result<(), String> {
+    if let Some(threshold) = query.threshold {
+        if !(0.0..=1.0).contains(&threshold) {
+            return Err(format!(
+                "threshold must be between 0 and 1, got {}",
+                threshold
+            ));
+        }
+    }
+    if let Some(timeout_ms) = query.timeout_ms {
+        if timeout_ms > MAX_TIMEOUT_MS {
+            return Err(format!("timeout_ms must be at most {}", MAX_TIMEOUT_MS));
+        }
+    }
+    Ok(())
+}
+
+/// 反复截图，直到变化像素的比例超过 `query.threshold` 或者超时
+///
+/// `etag` 将像素哈希换算为响应的 `ETag`，用于查找 `If-None-Match` 对应的截图。
+pub(super) async fn wait_for_change(
+    app_handle: &AppHandle,
+    capturer: &Arc<Capturer>,
+    region: Option<&str>,
+    query: &CaptureQuery,
+    etag: impl Fn(u64) -> String,
+    if_none_match: Option<&str>,
+) -> Result<Waited, CaptureFailure> {
+    let threshold = query.threshold.unwrap_or(0.0);
+    let timeout = Duration::from_millis(query.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS));
+    let deadline = Instant::now() + timeout;
+
+    let mut reference: Option<(u64, Arc<RgbaImage>)> = match if_none_match {
+        Some(if_none_match) => capturer.find(|hash| etag_matches(Some(if_none_match), &etag(hash))),
+        None => capturer.previous(region, query),
+    };
+
+    loop {
+        // 复用 `max_age` 内的缓存截图会在整个有效期内看不到变化
+        let captured = capturer.capture_fresh(app_handle, region, query).await?;
+        match &reference {
+            Some((hash, image)) => {
+                if captured.hash != *hash
+                    && diff::compare(image, &captured.image).changed_ratio() > threshold
+                {
+                    return Ok(Waited::Changed(captured));
+                }
+            }
+            None => {
+                // 客户端的 `ETag` 已经不在历史记录中，无法计算差异，只能按哈希判断
+                if if_none_match
+                    .is_some_and(|value| !etag_matches(Some(value), &etag(captured.hash)))
+                {
+                    return Ok(Waited::Changed(captured));
+                }
+                reference = Some((captured.hash, captured.image.clone()));
+            }
+        }
+
+        if Instant::now() >= deadline {
+            let hash = reference.map(|(hash, _)| hash).unwrap_or(captured.hash);
+            return Ok(Waited::Unchanged { hash });
+        }
+        tokio::time::sleep_until((Instant::now() + SAMPLE_INTERVAL).min(deadline)).await;
+    }
+}
+
+/// 从 `captured` 开始反复截图，直到画面连续 `settle_ms` 没有变化；超过 `max_wait_ms` 时返回最后一帧
+pub(super) async fn wait_for_settle(
+    app_handle: &AppHandle,
+    capturer: &Arc<Capturer>,
+    region: Option<&str>,
+    query: &CaptureQuery,
+    captured: CapturedFrame,
+    settle: &SettleOptions,
+) -> Result<CapturedFrame, CaptureFailure> {
+    let mut settler = Settler::new(settle);
+    let mut captured = captured;
+    while !settler.sample(captured.hash) {
+        tokio::time::sleep(settler.interval()).await;
+        // 复用缓存的截图会被误认为画面没有变化
+        captured = capturer.capture_fresh(app_handle, region, query).await?;
+    }
+    Ok(captured)
+}
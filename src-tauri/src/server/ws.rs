@@ -0,0 +1,351 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+//! `/ws`：通过 WebSocket 推送截图
+//!
+//! 每一帧先发送一条 JSON 文本消息描述截图信息，紧接着发送一条二进制消息为编码后的图片。
+//! 客户端可以随时发送 JSON 控制消息修改区域、格式和推送方式，或者请求立即截图。
+//! 发送是逐帧等待的，客户端读得慢时错过的定时截图直接跳过，不会在服务端排队。
+//! 服务停止，或者连接建立之后才开启令牌认证时，服务端关闭连接。
+
+use super::{encode_captured, CaptureFailure, CaptureQuery, Capturer, Shutdown};
+use crate::encode::EncodeOptions;
+use crate::geometry::ScreenRect;
+use crate::resize::ResizeOptions;
+use crate::{regions, RecorderState};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+use tokio::time::MissedTickBehavior;
+use warp::http::Response;
+use warp::hyper::Body;
+use warp::ws::{Message, WebSocket, Ws};
+use warp::Reply;
+
+const DEFAULT_INTERVAL_MS: u64 = 500;
+const MIN_INTERVAL_MS: u64 = 33;
+
+/// 关闭连接时的状态码，见 RFC 6455 7.4.1
+const CLOSE_GOING_AWAY: u16 = 1001;
+const CLOSE_POLICY_VIOLATION: u16 = 1008;
+
+/// 推送方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum PushMode {
+    /// 按 `interval_ms` 截图，只有画面变化时才推送
+    #[default]
+    Change,
+    /// 按 `interval_ms` 截图并推送每一帧
+    Interval,
+}
+
+/// `/ws` 特有的查询参数，区域、缩放和格式参数与 `/capture_screen` 相同
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct WsQuery {
+    mode: Option<PushMode>,
+    /// 截图间隔，默认 500 毫秒，最小 33 毫秒
+    interval_ms: Option<u64>,
+}
+
+/// 客户端发送的控制消息
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ControlMessage {
+    /// 修改截图区域，字段与 `/capture_screen` 的查询参数相同，全部省略时截取覆盖窗口
+    SetRegion {
+        #[serde(flatten)]
+        query: CaptureQuery,
+    },
+    /// 修改输出格式
+    SetFormat {
+        #[serde(flatten)]
+        encode: EncodeOptions,
+    },
+    /// 修改缩放参数
+    SetResize {
+        #[serde(flatten)]
+        resize: ResizeOptions,
+    },
+    /// 修改推送方式，省略的字段保持不变
+    SetMode {
+        mode: Option<PushMode>,
+        interval_ms: Option<u64>,
+    },
+    /// 立即截图并推送，不论画面是否变化
+    Snapshot,
+}
+
+/// 服务端发送的文本消息
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    /// 紧随其后的二进制消息的截图信息，字段含义与 JSON 截图响应相同
+    Frame {
+        content_type: &'static str,
+        width: u32,
+        height: u32,
+        scale_factor: f64,
+        rect: ScreenRect,
+        timestamp: u64,
+        sequence: u64,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl ServerMessage {
+    fn into_message(self) -> Message {
+        Message::text(serde_json::to_string(&self).unwrap())
+    }
+}
+
+/// 一个 WebSocket 连接的推送状态
+struct Session {
+    app_handle: AppHandle,
+    capturer: Arc<Capturer>,
+    region: Option<String>,
+    query: CaptureQuery,
+    resize: ResizeOptions,
+    encode: EncodeOptions,
+    mode: PushMode,
+    interval: Duration,
+    /// 上一次推送的图片的哈希，`change` 模式下用于判断画面是否变化
+    last_hash: Option<u64>,
+    /// 上一次发送的错误，同一个错误只发送一次
+    last_error: Option<String>,
+    /// 连接建立时是否校验过令牌，没有校验过的连接在开启令牌认证后关闭
+    token_checked: bool,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn handle_ws(
+    app_handle: AppHandle,
+    capturer: Arc<Capturer>,
+    shutdown: Shutdown,
+    region: Option<String>,
+    query: CaptureQuery,
+    resize: ResizeOptions,
+    encode: EncodeOptions,
+    ws_query: WsQuery,
+    ws: Ws,
+) -> Result<Response<Body>, warp::Rejection> {
+    let interval = match interval_from_ms(ws_query.interval_ms) {
+        Ok(interval) => interval,
+        Err(message) => return Ok(super::bad_request(message)),
+    };
+    if let Err(message) = resize.validate().and_then(|_| encode.validate()) {
+        return Ok(super::bad_request(message));
+    }
+    if regions::region_window(&app_handle, region.as_deref()).is_none() {
+        return Err(warp::reject::not_found());
+    }
+
+    let token_checked = app_handle
+        .state::<RecorderState>()
+        .require_token
+        .load(Ordering::SeqCst);
+    let session = Session {
+        app_handle,
+        capturer,
+        region,
+        query,
+        resize,
+        encode,
+        mode: ws_query.mode.unwrap_or_default(),
+        interval,
+        last_hash: None,
+        last_error: None,
+        token_checked,
+    };
+    Ok(ws
+        .on_upgrade(move |socket| session.run(socket, shutdown))
+        .into_response())
+}
+
+fn interval_from_ms(interval_ms: Option<u64>) -> Result<Duration, String> {
+    let interval_ms = interval_ms.unwrap_or(DEFAULT_INTERVAL_MS);
+    if interval_ms < MIN_INTERVAL_MS {
+        return Err(format!("interval_ms must be at least {}", MIN_INTERVAL_MS));
+    }
+    Ok(Duration::from_millis(interval_ms))
+}
+
+impl Session {
+    async fn run(mut self, socket: WebSocket, mut shutdown: Shutdown) {
+        let (mut sender, mut receiver) = socket.split();
+        let mut ticker = tokio::time::interval(self.interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+        loop {
+            let result = tokio::select! {
+                _ = shutdown.wait() => {
+                    close(&mut sender, CLOSE_GOING_AWAY, "Server is shutting down").await;
+                    break;
+                }
+                _ = ticker.tick() => {
+                    if self.token_now_required() {
+                        close(&mut sender, CLOSE_POLICY_VIOLATION, "Token required").await;
+                        break;
+                    }
+                    self.push(&mut sender, false).await
+                }
+                message = receiver.next() => match message {
+                    Some(Ok(message)) if message.is_text() => {
+                        let text = message.to_str().unwrap_or_default();
+                        match serde_json::from_str::<ControlMessage>(text) {
+                            Ok(control) => self.control(control, &mut sender, &mut ticker).await,
+                            Err(e) => {
+                                let message = format!("Invalid control message: {}", e);
+                                send_error(&mut sender, message).await
+                            }
+                        }
+                    }
+                    Some(Ok(message)) if message.is_close() => break,
+                    // ping 由 warp 自动回复，二进制消息忽略
+                    Some(Ok(_)) => Ok(true),
+                    Some(Err(_)) | None => break,
+                },
+            };
+            if !matches!(result, Ok(true)) {
+                break;
+            }
+        }
+    }
+
+    /// 连接建立之后才开启了令牌认证
+    fn token_now_required(&self) -> bool {
+        !self.token_checked
+            && self
+                .app_handle
+                .state::<RecorderState>()
+                .require_token
+                .load(Ordering::SeqCst)
+    }
+
+    /// 处理控制消息，参数无效时发送错误并保留原来的设置
+    async fn control(
+        &mut self,
+        control: ControlMessage,
+        sender: &mut Sender,
+        ticker: &mut tokio::time::Interval,
+    ) -> Result<bool, warp::Error> {
+        match control {
+            ControlMessage::SetRegion { query } => self.query = query,
+            ControlMessage::SetFormat { encode } => {
+                if let Err(message) = encode.validate() {
+                    return send_error(sender, message).await;
+                }
+                self.encode = encode;
+            }
+            ControlMessage::SetResize { resize } => {
+                if let Err(message) = resize.validate() {
+                    return send_error(sender, message).await;
+                }
+                self.resize = resize;
+            }
+            ControlMessage::SetMode { mode, interval_ms } => {
+                if let Some(interval_ms) = interval_ms {
+                    match interval_from_ms(Some(interval_ms)) {
+                        Ok(interval) => self.interval = interval,
+                        Err(message) => return send_error(sender, message).await,
+                    }
+                    *ticker = tokio::time::interval(self.interval);
+                    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+                }
+                self.mode = mode.unwrap_or(self.mode);
+            }
+            ControlMessage::Snapshot => return self.push(sender, true).await,
+        }
+        // 设置变化后立即推送一帧新的截图
+        self.last_hash = None;
+        self.last_error = None;
+        ticker.reset_immediately();
+        Ok(true)
+    }
+
+    /// 截图并推送，`change` 模式下画面没有变化时不推送；返回 `false` 时结束连接
+    async fn push(&mut self, sender: &mut Sender, force: bool) -> Result<bool, warp::Error> {
+        let result = match self
+            .capturer
+            .capture(&self.app_handle, self.region.as_deref(), &self.query)
+            .await
+        {
+            // 画面没有变化时不缩放和编码
+            Ok(captured)
+                if !force
+                    && self.mode == PushMode::Change
+                    && self.last_hash == Some(captured.hash) =>
+            {
+                self.last_error = None;
+                return Ok(true);
+            }
+            Ok(captured) => {
+                encode_captured(&self.app_handle, captured, self.resize, self.encode).await
+            }
+            Err(failure) => Err(failure),
+        };
+
+        let (captured, frame) = match result {
+            Ok(result) => result,
+            Err(CaptureFailure::WindowNotFound) => {
+                send_error(sender, "Region window not found".to_string()).await?;
+                return Ok(false);
+            }
+            Err(CaptureFailure::InvalidRegion(message) | CaptureFailure::Failed(message)) => {
+                if self.last_error.as_ref() != Some(&message) {
+                    self.last_error = Some(message.clone());
+                    send_error(sender, message).await?;
+                }
+                return Ok(true);
+            }
+            // 其他请求占满了截图队列，等下一次定时截图
+            Err(CaptureFailure::Busy) => return Ok(true),
+        };
+        self.last_error = None;
+        self.last_hash = Some(captured.hash);
+
+        let metadata = ServerMessage::Frame {
+            content_type: frame.format.content_type(),
+            width: frame.width,
+            height: frame.height,
+            scale_factor: captured.scale_factor,
+            rect: captured.rect,
+            timestamp: captured.timestamp,
+            sequence: captured.sequence,
+        };
+        sender.send(metadata.into_message()).await?;
+        let image_bytes = frame.bytes.len();
+        sender.send(Message::binary(frame.bytes)).await?;
+        let state = self.app_handle.state::<RecorderState>();
+        state.metrics.add_served_bytes(image_bytes);
+        Ok(true)
+    }
+}
+
+type Sender = futures_util::stream::SplitSink<WebSocket, Message>;
+
+/// 发送关闭帧并关闭连接，客户端已经断开时忽略错误
+async fn close(sender: &mut Sender, code: u16, reason: &'static str) {
+    sender.send(Message::close_with(code, reason)).await.ok();
+    sender.close().await.ok();
+}
+
+async fn send_error(sender: &mut Sender, message: String) -> Result<bool, warp::Error> {
+    sender
+        .send(ServerMessage::Error { message }.into_message())
+        .await?;
+    Ok(true)
+}
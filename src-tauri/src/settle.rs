@@ -0,0 +1,176 @@
+// Copyright (c) 2025 tommyZZM
+// tommys-comfy-screen-capturer is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY KIND,
+// EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO NON-INFRINGEMENT,
+// MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+//
+
+//! 等待画面稳定，避免截到绘制到一半的笔画或者正在淡入淡出的界面
+//!
+//! 调用方反复截图并把每一帧的哈希交给 `Settler`，画面连续 `settle_ms` 没有变化时即为稳定。
+
+use std::time::{Duration, Instant};
+
+const MAX_SETTLE_MS: u64 = 60_000;
+const DEFAULT_MAX_WAIT_MS: u64 = 5_000;
+const MAX_MAX_WAIT_MS: u64 = 300_000;
+const MIN_SAMPLE_INTERVAL: Duration = Duration::from_millis(33);
+const MAX_SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy)]
+pub struct SettleOptions {
+    /// 画面需要保持不变的时间
+    pub settle_ms: u64,
+    /// 最多等待的时间，默认 5000 毫秒，超时后返回最后一帧
+    pub max_wait_ms: Option<u64>,
+}
+
+impl SettleOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.settle_ms > MAX_SETTLE_MS {
+            return Err(format!("settle_ms must be at most {}", MAX_SETTLE_MS));
+        }
+        if self
+            .max_wait_ms
+            .is_some_and(|max_wait_ms| max_wait_ms > MAX_MAX_WAIT_MS)
+        {
+            return Err(format!("max_wait_ms must be at most {}", MAX_MAX_WAIT_MS));
+        }
+        Ok(())
+    }
+}
+
+pub struct Settler {
+    settle: Duration,
+    deadline: Instant,
+    /// 当前画面第一次出现的时间
+    stable_since: Instant,
+    last_hash: Option<u64>,
+}
+
+impl Settler {
+    pub fn new(options: &SettleOptions) -> Self {
+        let now = Instant::now();
+        let max_wait = options.max_wait_ms.unwrap_or(DEFAULT_MAX_WAIT_MS);
+        Settler {
+            settle: Duration::from_millis(options.settle_ms),
+            deadline: now + Duration::from_millis(max_wait),
+            stable_since: now,
+            last_hash: None,
+        }
+    }
+
+    /// 记录一帧截图的哈希，返回 `true` 时这一帧已经稳定或者已经超时
+    pub fn sample(&mut self, hash: u64) -> bool {
+        let now = Instant::now();
+        if self.last_hash != Some(hash) {
+            self.last_hash = Some(hash);
+            self.stable_since = now;
+        }
+        now.duration_since(self.stable_since) >= self.settle || now >= self.deadline
+    }
+
+    /// 下一次截图前等待的时间，约为 `settle_ms` 的四分之一
+    pub fn interval(&self) -> Duration {
+        (self.settle / 4).clamp(MIN_SAMPLE_INTERVAL, MAX_SAMPLE_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settler(settle_ms: u64, max_wait_ms: Option<u64>) -> Settler {
+        Settler::new(&SettleOptions {
+            settle_ms,
+            max_wait_ms,
+        })
+    }
+
+    /// 假装当前画面已经保持了 `ms` 毫秒
+    fn age(settler: &mut Settler, ms: u64) {
+        settler.stable_since -= Duration::from_millis(ms);
+    }
+
+    #[test]
+    fn zero_settle_returns_the_first_frame() {
+        let mut settler = settler(0, None);
+        assert!(settler.sample(1));
+    }
+
+    #[test]
+    fn settles_once_the_frame_stays_unchanged() {
+        let mut settler = settler(500, None);
+        assert!(!settler.sample(1));
+        assert!(!settler.sample(1));
+        age(&mut settler, 500);
+        assert!(settler.sample(1));
+    }
+
+    #[test]
+    fn changing_hash_resets_stable_since() {
+        let mut settler = settler(500, None);
+        assert!(!settler.sample(1));
+        age(&mut settler, 400);
+        let before = settler.stable_since;
+
+        assert!(!settler.sample(2));
+        assert!(settler.stable_since > before);
+        assert_eq!(settler.last_hash, Some(2));
+
+        // 原来的 400 毫秒不再计入
+        age(&mut settler, 400);
+        assert!(!settler.sample(2));
+        age(&mut settler, 100);
+        assert!(settler.sample(2));
+    }
+
+    #[test]
+    fn deadline_returns_the_last_frame() {
+        let mut settler = settler(MAX_SETTLE_MS, Some(1_000));
+        assert!(!settler.sample(1));
+        settler.deadline = Instant::now();
+        assert!(settler.sample(2));
+    }
+
+    #[test]
+    fn zero_max_wait_returns_immediately() {
+        let mut settler = settler(1_000, Some(0));
+        assert!(settler.sample(1));
+    }
+
+    #[test]
+    fn max_wait_defaults_to_five_seconds() {
+        let settler = settler(100, None);
+        assert_eq!(
+            settler.deadline - settler.stable_since,
+            Duration::from_millis(DEFAULT_MAX_WAIT_MS)
+        );
+    }
+
+    #[test]
+    fn interval_is_a_quarter_of_settle_within_limits() {
+        assert_eq!(settler(400, None).interval(), Duration::from_millis(100));
+        assert_eq!(settler(0, None).interval(), MIN_SAMPLE_INTERVAL);
+        assert_eq!(settler(100, None).interval(), MIN_SAMPLE_INTERVAL);
+        assert_eq!(settler(10_000, None).interval(), MAX_SAMPLE_INTERVAL);
+    }
+
+    #[test]
+    fn validates_limits() {
+        let options = |settle_ms, max_wait_ms| SettleOptions {
+            settle_ms,
+            max_wait_ms,
+        };
+        assert!(options(0, None).validate().is_ok());
+        assert!(options(MAX_SETTLE_MS, Some(MAX_MAX_WAIT_MS))
+            .validate()
+            .is_ok());
+        assert!(options(MAX_SETTLE_MS + 1, None).validate().is_err());
+        assert!(options(0, Some(MAX_MAX_WAIT_MS + 1)).validate().is_err());
+    }
+}
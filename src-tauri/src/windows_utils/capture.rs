@@ -12,11 +12,14 @@
 use image::RgbaImage;
 use scopeguard::guard;
 use std::mem;
-use tracing;
-use windows::Win32::Foundation::POINT;
+use windows::Win32::Foundation::{POINT, RECT};
+use windows::Win32::Graphics::Gdi::{
+    FillRect, GetObjectW, GetStockObject, BLACK_BRUSH, HBRUSH, WHITE_BRUSH,
+};
 use windows::Win32::UI::WindowsAndMessaging::{
-    GetWindowLongW, SetLayeredWindowAttributes, SetWindowLongW, GWL_EXSTYLE, LWA_COLORKEY,
-    WS_EX_LAYERED,
+    DrawIconEx, GetCursorInfo, GetIconInfo, GetWindowLongW, SetLayeredWindowAttributes,
+    SetWindowLongW, CURSORINFO, CURSOR_SHOWING, DI_NORMAL, GWL_EXSTYLE, HICON, ICONINFO,
+    LWA_COLORKEY, WS_EX_LAYERED,
 };
 use windows::Win32::{
     Foundation::{GetLastError, HWND},
@@ -113,7 +116,8 @@ fn delete_bitmap_object(val: HBITMAP) {
     }
 }
 
-pub fn capture_screen(target_hwnd: HWND, scale_factor: f32) -> XCapResult<RgbaImage> {
+/// 隐藏目标窗口并设置为透明，返回原来的 GWL_EXSTYLE 以便之后通过 `restore_window` 恢复
+pub fn hide_window(target_hwnd: HWND) -> i32 {
     unsafe {
         // Hide the target window
         ShowWindow(target_hwnd, SW_HIDE);
@@ -128,12 +132,56 @@ pub fn capture_screen(target_hwnd: HWND, scale_factor: f32) -> XCapResult<RgbaIm
             LWA_COLORKEY,
         );
 
-        let desktop_hwnd = GetDesktopWindow();
+        ex_style
+    }
+}
+
+pub fn restore_window(target_hwnd: HWND, ex_style: i32) {
+    unsafe {
+        // Restore the window's original style
+        SetWindowLongW(target_hwnd, GWL_EXSTYLE, ex_style);
+        ShowWindow(target_hwnd, SW_SHOW);
+    }
+}
+
+/// 窗口客户区左上角在屏幕上的位置，以及 `GetClientRect` 返回的客户区大小
+pub fn get_client_screen_rect(target_hwnd: HWND) -> (i32, i32, i32, i32) {
+    unsafe {
+        let mut rect = mem::zeroed();
+        GetClientRect(target_hwnd, &mut rect);
+
+        let mut top_left = POINT {
+            x: rect.left,
+            y: rect.top,
+        };
+        ClientToScreen(target_hwnd, &mut top_left);
+
+        (
+            top_left.x,
+            top_left.y,
+            rect.right - rect.left,
+            rect.bottom - rect.top,
+        )
+    }
+}
+
+/// 目标窗口所在显示器的范围 (left, top, right, bottom)
+pub fn get_monitor_rect(target_hwnd: HWND) -> (i32, i32, i32, i32) {
+    unsafe {
         let h_monitor = MonitorFromWindow(target_hwnd, MONITOR_DEFAULTTONEAREST);
         let mut monitor_info = MONITORINFO::default();
         monitor_info.cbSize = std::mem::size_of::<MONITORINFO>() as u32;
         GetMonitorInfoW(h_monitor, &mut monitor_info);
 
+        let rect = monitor_info.rcMonitor;
+        (rect.left, rect.top, rect.right, rect.bottom)
+    }
+}
+
+pub fn capture_rect(x: i32, y: i32, width: i32, height: i32) -> XCapResult<RgbaImage> {
+    unsafe {
+        let desktop_hwnd = GetDesktopWindow();
+
         let h_monitor = guard(GetWindowDC(Some(desktop_hwnd)), |val| {
             if ReleaseDC(Some(desktop_hwnd), val) != 1 {
                 tracing::error!("ReleaseDC({:?}) failed: {:?}", val, GetLastError());
@@ -148,29 +196,12 @@ pub fn capture_screen(target_hwnd: HWND, scale_factor: f32) -> XCapResult<RgbaIm
             }
         });
 
-        let mut rect = mem::zeroed();
-        GetClientRect(target_hwnd, &mut rect);
-
-        let mut top_left = POINT {
-            x: rect.left,
-            y: rect.top,
-        };
-        ClientToScreen(target_hwnd, &mut top_left);
-
-        let width = ((rect.right - rect.left) as f32 * scale_factor).ceil() as i32;
-        let height = ((rect.bottom - rect.top) as f32 * scale_factor).ceil() as i32;
-
         let h_bitmap = guard(
             CreateCompatibleBitmap(*h_monitor, width, height),
             delete_bitmap_object,
         );
         SelectObject(*hdc_mem, (*h_bitmap).into());
 
-        println!(
-            "width: {}, height: {} rect.left: {} rect.top: {}",
-            width, height, rect.left, rect.top
-        );
-
         BitBlt(
             *hdc_mem,
             0,
@@ -178,15 +209,115 @@ pub fn capture_screen(target_hwnd: HWND, scale_factor: f32) -> XCapResult<RgbaIm
             width,
             height,
             Some(*h_monitor),
-            top_left.x,
-            top_left.y,
+            x,
+            y,
             SRCCOPY,
         );
 
-        // Restore the window's original style
-        SetWindowLongW(target_hwnd, GWL_EXSTYLE, ex_style);
-        ShowWindow(target_hwnd, SW_SHOW);
-
         to_rgba_image(*hdc_mem, *h_bitmap, width, height)
     }
 }
+
+/// 当前鼠标指针的图像，以及图像左上角（已减去热点偏移）在屏幕上的位置
+///
+/// 指针分别绘制在黑色和白色背景上，通过两次结果的差值还原 alpha，
+/// 这样带 alpha 的彩色指针和只有掩码的单色指针都能正确处理。
+/// 指针隐藏时返回 `Ok(None)`。
+pub fn capture_cursor() -> XCapResult<Option<(i32, i32, RgbaImage)>> {
+    unsafe {
+        let mut cursor_info = CURSORINFO {
+            cbSize: mem::size_of::<CURSORINFO>() as u32,
+            ..Default::default()
+        };
+        GetCursorInfo(&mut cursor_info).map_err(XCapError::new)?;
+        if cursor_info.flags.0 & CURSOR_SHOWING.0 == 0 || cursor_info.hCursor.is_invalid() {
+            return Ok(None);
+        }
+
+        let h_icon = HICON(cursor_info.hCursor.0);
+        let mut icon_info = ICONINFO::default();
+        GetIconInfo(h_icon, &mut icon_info).map_err(XCapError::new)?;
+
+        // GetIconInfo 创建的位图需要调用方释放，单色指针没有 hbmColor
+        let has_color = !icon_info.hbmColor.is_invalid();
+        let hbm_mask = guard(icon_info.hbmMask, delete_bitmap_object);
+        let hbm_color = guard(icon_info.hbmColor, |val| {
+            if !val.is_invalid() {
+                delete_bitmap_object(val);
+            }
+        });
+
+        let mut bitmap = BITMAP::default();
+        let source = if has_color { *hbm_color } else { *hbm_mask };
+        GetObjectW(
+            source.into(),
+            mem::size_of::<BITMAP>() as i32,
+            Some(&mut bitmap as *mut BITMAP as *mut _),
+        );
+        let width = bitmap.bmWidth;
+        // 单色指针的掩码位图上半部分为 AND 掩码，下半部分为 XOR 掩码
+        let height = if has_color {
+            bitmap.bmHeight
+        } else {
+            bitmap.bmHeight / 2
+        };
+        if width <= 0 || height <= 0 {
+            return Ok(None);
+        }
+
+        let desktop_hwnd = GetDesktopWindow();
+        let h_screen = guard(GetWindowDC(Some(desktop_hwnd)), |val| {
+            if ReleaseDC(Some(desktop_hwnd), val) != 1 {
+                tracing::error!("ReleaseDC({:?}) failed: {:?}", val, GetLastError());
+            }
+        });
+        let hdc_mem = guard(CreateCompatibleDC(Some(*h_screen)), |val| {
+            if !DeleteDC(val).as_bool() {
+                tracing::error!("DeleteDC({:?}) failed: {:?}", val, GetLastError());
+            }
+        });
+        let h_bitmap = guard(
+            CreateCompatibleBitmap(*h_screen, width, height),
+            delete_bitmap_object,
+        );
+        SelectObject(*hdc_mem, (*h_bitmap).into());
+
+        let rect = RECT {
+            left: 0,
+            top: 0,
+            right: width,
+            bottom: height,
+        };
+        let render = |background| -> XCapResult<RgbaImage> {
+            FillRect(*hdc_mem, &rect, HBRUSH(GetStockObject(background).0));
+            DrawIconEx(*hdc_mem, 0, 0, h_icon, width, height, 0, None, DI_NORMAL)
+                .map_err(XCapError::new)?;
+            to_rgba_image(*hdc_mem, *h_bitmap, width, height)
+        };
+        let on_black = render(BLACK_BRUSH)?;
+        let on_white = render(WHITE_BRUSH)?;
+
+        let image = RgbaImage::from_fn(width as u32, height as u32, |x, y| {
+            let black = on_black.get_pixel(x, y).0;
+            let white = on_white.get_pixel(x, y).0;
+            // 白底减黑底 = 255 - alpha
+            let alpha = 255 - (white[1] as i32 - black[1] as i32).clamp(0, 255);
+            if alpha == 0 {
+                return image::Rgba([0, 0, 0, 0]);
+            }
+            let unpremultiply = |c: u8| (c as i32 * 255 / alpha).min(255) as u8;
+            image::Rgba([
+                unpremultiply(black[0]),
+                unpremultiply(black[1]),
+                unpremultiply(black[2]),
+                alpha as u8,
+            ])
+        });
+
+        Ok(Some((
+            cursor_info.ptScreenPos.x - icon_info.xHotspot as i32,
+            cursor_info.ptScreenPos.y - icon_info.yHotspot as i32,
+            image,
+        )))
+    }
+}
@@ -12,7 +12,7 @@
 /** @jsxImportSource @emotion/react */
 import { css } from "@emotion/react";
 import { useState, useEffect, useMemo, useRef } from "react";
-import { Modal, InputNumber, Button, Form, Input, message, Switch, Spin } from "antd";
+import { Modal, InputNumber, Button, Form, Input, message, Switch, Spin, Select } from "antd";
 import { FontAwesomeIcon } from '@fortawesome/react-fontawesome';
 import { faThumbtack, faThumbtackSlash, faCamera, faSave, faCheck, faCircleXmark, faGears, faServer, faArrowsAlt, faXmark } from '@fortawesome/free-solid-svg-icons';
 import { invoke } from '@tauri-apps/api/core';
@@ -31,16 +31,28 @@ const BASE_WINDOW_TITLE = packageJson.app.windows[0].title;
 
 const DEFAULT_PORT = 12666;
 
+// 调整窗口大小时将截图尺寸取整到 Stable Diffusion 友好的尺寸
+const SNAP_OPTIONS = [
+  { value: '8', label: 'Multiple of 8' },
+  { value: '64', label: 'Multiple of 64' },
+  { value: 'sdxl', label: 'SDXL bucket' },
+  { value: 'flux', label: 'Flux bucket' },
+];
+
 function getWindowRandomId() {
   return Math.random().toString(36).substring(2, 9);
 }
 
-function getUrlCaptureScreen(port) {
-  return `http://127.0.0.1:${port}/capture_screen`;
+// 开启令牌认证时 URL 中带上令牌；host 为 --bind 指定的监听地址
+function getUrlCaptureScreen(port, regionName, token, host = '127.0.0.1') {
+  const path = regionName ? `/capture_screen/${regionName}` : '/capture_screen';
+  const query = token ? `?token=${encodeURIComponent(token)}` : '';
+  return `http://${host}:${port}${path}${query}`;
 }
 
 function App() {
   const refServerStarted = useRef(false);
+  const refRegionName = useRef(null);
   const [changed, setChanged] = useState(0);
   const [isPendingInitial, setIsPendingInitial] = useState(true);
   const [windowId, setWindowId] = useState(getWindowRandomId());
@@ -53,8 +65,11 @@ function App() {
   const [newHeight, setNewHeight] = useState(window.innerHeight);
   const [isPendingCaptureScreenByButton, setIsPendingCaptureScreenByButton] = useState(false);
   const [serverStarted, setServerStarted] = useState(false);
+  const [apiToken, setApiToken] = useState(null); // 不需要令牌时为 null
+  const [serverHost, setServerHost] = useState(undefined);
 
   const [isPin, setIsPin] = useState(false); // 添加 isPin 状态
+  const [regionName, setRegionName] = useState(null); // 命名区域窗口的名称，主窗口为 null
   const [isPreviewImageVisible, setIsPreviewImageVisible] = useState(false);
   const [base64PreviewImage, setBase64PreviewImage] = useState(null);
   const [form] = Form.useForm();
@@ -79,12 +94,51 @@ function App() {
     window.addEventListener("resize", handleResize);
 
     const pending = (async () => {
+      setServerHost(await invoke('get_server_host'));
+      const name = await invoke('get_region_name');
+      refRegionName.current = name;
+      setRegionName(name);
+
+      // 命名区域窗口：服务器和图钉由主窗口控制，这里只同步名称和端口
+      if (name) {
+        const unlisten_regions_changed = await listen('regions_changed', async () => {
+          const name = await invoke('get_region_name');
+          refRegionName.current = name;
+          setRegionName(name);
+          form.setFieldValue('regionName', name);
+        });
+
+        const unlisten_rename_region = await listen('rename_region', () => {
+          form.setFieldValue('regionName', refRegionName.current);
+          setIsResizeModalVisible(true);
+        });
+
+        const port = await invoke('get_server_port');
+        form.setFieldsValue({ regionName: name, ...port && { serverPort: port } });
+        setApiToken(await invoke('get_api_token'));
+
+        const isStartServerBackend = await invoke('get_is_server_running');
+        form.setFieldValue('isStartServer', isStartServerBackend);
+        setServerStarted(isStartServerBackend);
+
+        setIsPendingInitial(false);
+
+        return () => {
+          unlisten_regions_changed();
+          unlisten_rename_region();
+        }
+      }
+
       const unlisten_is_pin_changed = await listen('is_pin_changed', async (event) => {
         setIsPin(event.payload);
       });
 
       await invoke('set_is_pin', { isPin: false });
 
+      const token = await invoke('get_api_token');
+      setApiToken(token);
+      form.setFieldValue('requireToken', !!token);
+
       const unliste_copy_url_to_clipboard = await listen('copy_screenshot_url', () => {
         handleCopyUrlToClipboard(form.getFieldsValue().serverPort);
       });
@@ -109,18 +163,18 @@ function App() {
 
   useEffect(() => {
     (async () => {
-      await invoke("set_window_title", { title: BASE_WINDOW_TITLE + `[${windowId}]` });
+      await invoke("set_window_title", { title: BASE_WINDOW_TITLE + `[${regionName || windowId}]` });
     })();
-  }, [windowId]);
+  }, [windowId, regionName]);
 
   useEffect(() => {
     (async () => {
-      if (isPendingInitial) {
+      if (isPendingInitial || refRegionName.current) {
         return;
       }
       // console.log('serverStarted?', serverStarted, serverPort);
       if (serverStarted && serverPort) {
-        await invoke("restart_http_server", { port: serverPort, scaleFactor: window.devicePixelRatio });
+        await invoke("restart_http_server", { port: serverPort });
       } else {
         await invoke("stop_http_server");
       }
@@ -144,10 +198,22 @@ function App() {
     const values = await form.validateFields();
     setIsResizeModalVisible(false);
     const [width, height] = values.size;
-    await invoke("resize_window", { width, height });
+    await invoke("resize_window", { width, height, snap: values.snap || null });
+
+    if (regionName) {
+      if (values.regionName !== regionName) {
+        try {
+          await invoke("rename_region", { name: regionName, newName: values.regionName });
+        } catch (error) {
+          message.error(typeof error === "string" ? error : (error.message || "rename region failed."));
+        }
+      }
+      return;
+    }
 
     // Update serverStarted based on isStartServer value
     setServerStarted(values.isStartServer);
+    setApiToken(await invoke('set_require_token', { requireToken: !!values.requireToken }));
   };
 
   const handleResizeModalCancel = () => {
@@ -159,7 +225,7 @@ function App() {
 
     const encodedImage = await (async () => {
       try {
-        const res = await invoke('capture_window_screenshot', { scaleFactor: window.devicePixelRatio });
+        const res = await invoke('capture_window_screenshot');
         return res;
       } catch (error) {
         message.error(typeof error === "string" ? error : (error.message || "capture screen failed."));
@@ -203,8 +269,11 @@ function App() {
   };
 
   // 用于复制 URL 到剪贴板
-  const handleCopyUrlToClipboard = (port = serverPort) => {
-    const url = getUrlCaptureScreen(port);
+  const handleCopyUrlToClipboard = async (port = serverPort) => {
+    // 托盘菜单的回调中 apiToken 可能已经过时，这里重新获取
+    const token = await invoke('get_api_token');
+    const host = await invoke('get_server_host');
+    const url = getUrlCaptureScreen(port, refRegionName.current, token, host);
     writeText(url).then(() => {
       if (refServerStarted.current) {
         message.success("URL copied!");
@@ -302,7 +371,7 @@ function App() {
     align-items: center;
   `;
 
-  const urlCaptureScreen = useMemo(() => getUrlCaptureScreen(serverPort), [serverPort]);
+  const urlCaptureScreen = useMemo(() => getUrlCaptureScreen(serverPort, regionName, apiToken, serverHost), [serverPort, regionName, apiToken, serverHost]);
 
   return (
     <main 
@@ -311,10 +380,10 @@ function App() {
     >
       <Spin wrapperClassName={appLess.appSpinWrapper} spinning={isPendingInitial}>
         <div style={{ ...isPin && { opacity: 0 } }} css={windowTopbarStyle}>
-          <Button style={{ marginRight: 10 }} {...isPin && { type: 'primary' }} size={'small'} onClick={togglePin}>
+          {!regionName && <Button style={{ marginRight: 10 }} {...isPin && { type: 'primary' }} size={'small'} onClick={togglePin}>
             <FontAwesomeIcon icon={isPin ? faThumbtack : faThumbtackSlash} />
-          </Button>
-          <Button size={'small'} onClick={() => invoke('quit_app')}>
+          </Button>}
+          <Button size={'small'} onClick={() => regionName ? invoke('close_region', { name: regionName }) : invoke('quit_app')}>
             <FontAwesomeIcon icon={faXmark} />
           </Button>
         </div>
@@ -361,14 +430,23 @@ function App() {
             <Form.Item label="Size" name="size" rules={[{ required: true, message: 'Please input size!' }]}>
               <WidthHeightField size="small" />
             </Form.Item>
+            <Form.Item label="Snap" name="snap">
+              <Select size="small" allowClear placeholder="None" options={SNAP_OPTIONS} />
+            </Form.Item>
+            {regionName && <Form.Item label="Name" name="regionName" rules={[{ required: true, pattern: /^[A-Za-z0-9_-]{1,64}$/, message: 'Only letters, digits, - and _' }]}>
+              <Input size="small" />
+            </Form.Item>}
             <div style={{ height: 32, paddingBottom: 8 }} css={flexAlignStartStyle}>Server {isStartServer && <div style={{ marginLeft: 5 }}><small><a onClick={() => handleCopyUrlToClipboard(serverPort)}>{urlCaptureScreen}</a></small></div>}</div>
-            <Form.Item label={null} name="isStartServer" valuePropName="checked">
+            <Form.Item hidden={!!regionName} label={null} name="isStartServer" valuePropName="checked">
               <Switch onChange={() => setChanged(a => a + 1)}>Start Server</Switch>
             </Form.Item>
             
-            <Form.Item label="ServerPort" name="serverPort" rules={[{ required: true, message: 'Please input serverPort!' }]}>
+            <Form.Item hidden={!!regionName} label="ServerPort" name="serverPort" rules={[{ required: true, message: 'Please input serverPort!' }]}>
               <InputNumber onChange={() => setChanged(a => a + 1)} min={1} max={65535} />
             </Form.Item>
+            <Form.Item hidden={!!regionName} label="Require Token" name="requireToken" valuePropName="checked">
+              <Switch onChange={() => setChanged(a => a + 1)} />
+            </Form.Item>
           </Form>
           <div css={flexFillRestStyle} />
           <div style={{ fontSize: 12 }}>